@@ -0,0 +1,370 @@
+use crate::perm::{Pattern, Permissions};
+use anyhow::{anyhow, bail, Context as _};
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, RoomId};
+use mstickerlib::matrix::sticker_formats::ponies;
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use serde::Deserialize;
+use std::{
+	collections::HashMap,
+	env, fs,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	str::FromStr
+};
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Return the global config. Panics if the config has not been loaded yet.
+pub(crate) fn config() -> &'static Config {
+	CONFIG.get().expect("config has not been loaded")
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+	#[serde(default)]
+	pub(crate) matrix: MatrixConfig,
+
+	#[serde(default)]
+	pub(crate) telegram: TelegramConfig,
+
+	#[serde(default)]
+	pub(crate) permissions: Permissions,
+
+	#[serde(default)]
+	pub(crate) limits: Limits,
+
+	#[serde(default)]
+	pub(crate) fetch: FetchConfig,
+
+	#[serde(default)]
+	pub(crate) storage: Storage,
+
+	#[serde(default)]
+	pub(crate) rooms: HashMap<OwnedRoomId, RoomConfig>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MatrixConfig {
+	pub(crate) homeserver: Option<String>,
+	pub(crate) user: Option<OwnedUserId>,
+	pub(crate) password: Option<String>,
+	pub(crate) access_token: Option<String>,
+	pub(crate) device_name: String,
+	pub(crate) display_name: String
+}
+
+impl Default for MatrixConfig {
+	fn default() -> Self {
+		Self {
+			homeserver: None,
+			user: None,
+			password: None,
+			access_token: None,
+			device_name: "tg2mx bot".into(),
+			display_name: "TG2MX Sticker Import BOT".into()
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TelegramConfig {
+	pub(crate) bot_token: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Limits {
+	/// The maximum size of a downloaded sticker pack description in bytes.
+	pub(crate) max_content_length: usize,
+
+	/// The maximum size of a downloaded image in bytes.
+	pub(crate) max_file_size: usize,
+
+	/// The maximum size of all images in an uploaded zip archive after decompression,
+	/// in bytes.
+	pub(crate) max_archive_size: usize,
+
+	/// The maximum amount of sticker packs migrated from a stickerpicker index.
+	pub(crate) max_index_packs: usize,
+
+	/// The maximum amount of stickers in a migrated or uploaded sticker pack.
+	pub(crate) max_stickers: usize,
+
+	/// The maximum amount of jobs in the queue.
+	pub(crate) max_queue_len: usize
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			max_content_length: 100 * 1024,
+			max_file_size: 10 * 1024 * 1024,
+			max_archive_size: 100 * 1024 * 1024,
+			max_index_packs: 50,
+			max_stickers: 500,
+			max_queue_len: 100
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FetchConfig {
+	/// Hosts that may be fetched even if they resolve to a non-public address.
+	pub(crate) allowlist: Vec<String>,
+
+	/// The maximum amount of redirects to follow.
+	pub(crate) max_redirects: usize,
+
+	/// The timeout for a whole download in seconds.
+	pub(crate) timeout_secs: u64
+}
+
+impl Default for FetchConfig {
+	fn default() -> Self {
+		Self {
+			allowlist: Vec::new(),
+			max_redirects: 5,
+			timeout_secs: 30
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Storage {
+	pub(crate) session_file: PathBuf,
+	pub(crate) store_path: PathBuf,
+	pub(crate) store_passphrase: Option<String>
+}
+
+impl Default for Storage {
+	fn default() -> Self {
+		Self {
+			session_file: "session.json".into(),
+			store_path: "store".into(),
+			store_passphrase: None
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RoomConfig {
+	/// The usage of stickers imported into this room.
+	pub(crate) usage: Option<PackUsage>,
+
+	/// The power level required to manage the sticker packs of this room.
+	pub(crate) pack_power_level: Option<i64>
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PackUsage {
+	Sticker,
+	Emoticon,
+	Both
+}
+
+impl FromStr for PackUsage {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		Ok(match s {
+			"sticker" => Self::Sticker,
+			"emoticon" => Self::Emoticon,
+			"both" => Self::Both,
+			_ => bail!("Unknown usage {s}, expected sticker, emoticon or both")
+		})
+	}
+}
+
+impl PackUsage {
+	pub(crate) fn usage(self) -> Vec<ponies::Usage> {
+		match self {
+			Self::Sticker => vec![ponies::Usage::Sticker],
+			Self::Emoticon => vec![ponies::Usage::Emoticon],
+			Self::Both => vec![ponies::Usage::Sticker, ponies::Usage::Emoticon]
+		}
+	}
+}
+
+impl Config {
+	pub(crate) fn homeserver(&self) -> &str {
+		self.matrix.homeserver.as_deref().unwrap()
+	}
+
+	pub(crate) fn tg_bot_token(&self) -> &str {
+		self.telegram.bot_token.as_deref().unwrap()
+	}
+
+	/// Return the configuration of a room, or the defaults if the room has none.
+	pub(crate) fn room(&self, room_id: &RoomId) -> &RoomConfig {
+		static DEFAULT: RoomConfig = RoomConfig {
+			usage: None,
+			pack_power_level: None
+		};
+		self.rooms.get(room_id).unwrap_or(&DEFAULT)
+	}
+
+	fn read(path: &Path, required: bool) -> anyhow::Result<Self> {
+		let content = match fs::read_to_string(path) {
+			Ok(content) => content,
+			Err(err) if err.kind() == ErrorKind::NotFound && !required => {
+				return Ok(Self::default());
+			},
+			Err(err) => return Err(err.into())
+		};
+		Ok(toml::from_str(&content)?)
+	}
+
+	/// Apply the environment variables on top of the config file.
+	fn apply_env(&mut self) -> anyhow::Result<()> {
+		fn var(name: &str) -> Option<String> {
+			env::var(name).ok().filter(|value| !value.is_empty())
+		}
+
+		if let Some(homeserver) = var("HOMESERVER") {
+			self.matrix.homeserver = Some(homeserver);
+		}
+		if let Some(user) = var("MATRIX_ID") {
+			self.matrix.user = Some(
+				user.parse()
+					.with_context(|| format!("MATRIX_ID: Invalid user id {user:?}"))?
+			);
+		}
+		if let Some(password) = var("PASSWORD") {
+			self.matrix.password = Some(password);
+		}
+		if let Some(access_token) = var("ACCESS_TOKEN") {
+			self.matrix.access_token = Some(access_token);
+		}
+		if let Some(bot_token) = var("TG_BOT_TOKEN") {
+			self.telegram.bot_token = Some(bot_token);
+		}
+		if let Some(admins) = var("ADMIN") {
+			self.permissions.admin = admins
+				.split([',', ' '])
+				.filter(|admin| !admin.is_empty())
+				.map(Pattern::new)
+				.collect();
+		}
+		if let Some(session_file) = var("SESSION_FILE") {
+			self.storage.session_file = session_file.into();
+		}
+		if let Some(store_path) = var("STORE_PATH") {
+			self.storage.store_path = store_path.into();
+		}
+		if let Some(store_passphrase) = var("STORE_PASSPHRASE") {
+			self.storage.store_passphrase = Some(store_passphrase);
+		}
+		Ok(())
+	}
+
+	/// Check the config for errors, returning all of them at once.
+	fn validate(&self) -> Vec<String> {
+		let mut errors = Vec::new();
+
+		match &self.matrix.homeserver {
+			None => errors.push("matrix.homeserver: Missing homeserver url".to_owned()),
+			Some(homeserver) => match Url::parse(homeserver) {
+				Ok(url) if matches!(url.scheme(), "http" | "https") => {},
+				Ok(url) => errors.push(format!(
+					"matrix.homeserver: Unsupported url scheme {:?}",
+					url.scheme()
+				)),
+				Err(err) => errors.push(format!(
+					"matrix.homeserver: Invalid url {homeserver:?}: {err}"
+				))
+			}
+		}
+
+		// credentials are only needed if we have no session to restore
+		if self.matrix.access_token.is_none() && !self.storage.session_file.exists() {
+			if self.matrix.user.is_none() {
+				errors
+					.push("matrix.user: Missing user id for the first login".to_owned());
+			}
+			if self.matrix.password.is_none() {
+				errors.push(
+					"matrix.password: Missing password for the first login".to_owned()
+				);
+			}
+		}
+
+		if self
+			.telegram
+			.bot_token
+			.as_deref()
+			.is_none_or(|token| token.is_empty())
+		{
+			errors.push("telegram.bot_token: Missing telegram bot token".to_owned());
+		}
+
+		self.permissions.validate(&mut errors);
+
+		if self.limits.max_content_length == 0 {
+			errors.push("limits.max_content_length: Must not be zero".to_owned());
+		}
+		if self.limits.max_file_size == 0 {
+			errors.push("limits.max_file_size: Must not be zero".to_owned());
+		}
+		if self.limits.max_archive_size == 0 {
+			errors.push("limits.max_archive_size: Must not be zero".to_owned());
+		}
+		if self.limits.max_index_packs == 0 {
+			errors.push("limits.max_index_packs: Must not be zero".to_owned());
+		}
+		if self.limits.max_stickers == 0 {
+			errors.push("limits.max_stickers: Must not be zero".to_owned());
+		}
+		if self.limits.max_queue_len == 0 {
+			errors.push("limits.max_queue_len: Must not be zero".to_owned());
+		}
+
+		if self.fetch.timeout_secs == 0 {
+			errors.push("fetch.timeout_secs: Must not be zero".to_owned());
+		}
+		for host in &self.fetch.allowlist {
+			if host.is_empty() || host.contains(['/', '[', ']']) {
+				errors.push(format!(
+					"fetch.allowlist: {host:?} is not a host name or IP address"
+				));
+			}
+		}
+
+		if self.storage.store_path.is_file() {
+			errors.push(format!(
+				"storage.store_path: {} is a file, not a directory",
+				self.storage.store_path.display()
+			));
+		}
+
+		errors
+	}
+
+	/// Load the config file (if any), apply the environment variables, and validate
+	/// the result. The config is then available through [`config()`].
+	pub(crate) fn load(path: Option<&Path>) -> anyhow::Result<&'static Self> {
+		let mut config = match path {
+			Some(path) => Self::read(path, true),
+			None => Self::read(Path::new("config.toml"), false)
+		}
+		.context("Failed to read config file")?;
+		config.apply_env().context("Invalid environment variable")?;
+
+		let errors = config.validate();
+		if !errors.is_empty() {
+			bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+		}
+
+		CONFIG
+			.set(config)
+			.map_err(|_| anyhow!("Config has already been loaded"))?;
+		Ok(self::config())
+	}
+}
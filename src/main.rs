@@ -1,37 +1,42 @@
 #![warn(rust_2018_idioms, unreachable_pub)]
 #![deny(elided_lifetimes_in_paths, unsafe_code)]
 
-use anyhow::anyhow;
+use anyhow::bail;
 use dotenvy::dotenv;
-use once_cell::sync::Lazy;
-use std::env;
+use std::{env, path::PathBuf};
 
+mod config;
 mod mxbot;
+mod perm;
 
-macro_rules! env {
-	($($var:ident),*) => {
-		$(
-			static $var: Lazy<anyhow::Result<String>> = Lazy::new(|| {
-				env::var(stringify!($var)).map_err(|_| {
-					anyhow!("Missing {} environment variable", stringify!($var))
-				})
-			});
-		)*
-	};
-}
-
-env! {
-	ADMIN,
-	HOMESERVER,
-	MATRIX_ID,
-	PASSWORD,
-	TG_BOT_TOKEN
-}
+use config::Config;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	pretty_env_logger::init_timed();
 	dotenv().ok();
 
+	let mut config_path = env::var_os("TG2MX_CONFIG").map(PathBuf::from);
+	let mut check_config = false;
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--config" => {
+				let Some(path) = args.next() else {
+					bail!("Missing argument for --config");
+				};
+				config_path = Some(path.into());
+			},
+			"--check-config" => check_config = true,
+			_ => bail!("Unknown argument {arg:?}")
+		}
+	}
+
+	Config::load(config_path.as_deref())?;
+	if check_config {
+		println!("The configuration is valid.");
+		return Ok(());
+	}
+
 	mxbot::run().await
 }
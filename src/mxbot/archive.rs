@@ -0,0 +1,264 @@
+use super::{
+	db::AccountDataDatabase,
+	media::{download_file, media_url},
+	slug::{resolve_state_key, slugify, OnCollision},
+	split_note,
+	state::{
+		fill_avatar, unique_shortcode, write_stickerpack, PackSource, RoomStickerPack,
+		SourceType
+	}
+};
+use crate::config::{config, PackUsage};
+use anyhow::{anyhow, bail, Context as _};
+use heck::ToSnakeCase;
+use indexmap::IndexMap;
+use log::error;
+use matrix_sdk::{
+	room::Room,
+	ruma::{events::room::message::FileMessageEventContent, UserId}
+};
+use mstickerlib::matrix::sticker_formats::ponies;
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{Cursor, Read as _};
+use zip::ZipArchive;
+
+/// The name of the optional manifest in the root of the archive.
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Manifest {
+	title: Option<String>,
+
+	/// The stickers by file name, in the order they should appear in the pack. If
+	/// empty, all images of the archive are imported, ordered by their file name.
+	stickers: IndexMap<String, ManifestSticker>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestSticker {
+	shortcode: Option<String>,
+	body: Option<String>
+}
+
+struct Image {
+	name: String,
+	mimetype: &'static str,
+	data: Vec<u8>
+}
+
+impl Image {
+	/// The file name without directories and extension.
+	fn stem(&self) -> &str {
+		let name = self.name.rsplit('/').next().unwrap_or(&self.name);
+		name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+	}
+}
+
+fn image_type(name: &str) -> Option<&'static str> {
+	let (_, ext) = name.rsplit_once('.')?;
+	Some(match ext.to_ascii_lowercase().as_str() {
+		"png" => "image/png",
+		"webp" => "image/webp",
+		"gif" => "image/gif",
+		"jpg" | "jpeg" => "image/jpeg",
+		_ => return None
+	})
+}
+
+/// Whether the entry of the archive is skipped, i.e. a directory, a hidden file or the
+/// metadata added by macOS.
+fn is_skipped(name: &str) -> bool {
+	let hidden = name
+		.rsplit('/')
+		.next()
+		.is_some_and(|name| name.starts_with('.'));
+	name.ends_with('/') || hidden || name.starts_with("__MACOSX/")
+}
+
+/// Read the manifest and the images from the archive, in the order they should appear
+/// in the pack. Files larger than `max_len` bytes are rejected, as are archives with
+/// more than `max_images` images or whose files are larger than `max_total` bytes
+/// combined.
+fn read_archive(
+	data: Vec<u8>,
+	max_len: usize,
+	max_images: usize,
+	max_total: usize
+) -> anyhow::Result<(Manifest, Vec<Image>)> {
+	let mut archive =
+		ZipArchive::new(Cursor::new(data)).context("The file is not a zip archive")?;
+
+	// check the number of entries before decompressing anything, allowing for a
+	// directory or macOS metadata entry per image
+	if archive.len() > 2 * max_images + 1 {
+		bail!("The archive contains too many files");
+	}
+	let image_count = archive
+		.file_names()
+		.filter(|name| !is_skipped(name) && image_type(name).is_some())
+		.count();
+	if image_count > max_images {
+		bail!(
+			"The archive contains {image_count} images, but at most {max_images} are \
+			 allowed"
+		);
+	}
+
+	let mut manifest = Manifest::default();
+	let mut images = Vec::new();
+	let mut total = 0;
+	for i in 0 .. archive.len() {
+		let file = archive.by_index(i)?;
+		let name = file.name().to_owned();
+		if file.is_dir() || is_skipped(&name) {
+			continue;
+		}
+		let mimetype = image_type(&name);
+		if name != MANIFEST && mimetype.is_none() {
+			continue;
+		}
+
+		// don't trust the size from the archive, it could be a zip bomb
+		if file.size() > max_len as u64 {
+			bail!("{name} exceeds the maximum file size");
+		}
+		let mut data = Vec::new();
+		file.take(max_len as u64 + 1).read_to_end(&mut data)?;
+		if data.len() > max_len {
+			bail!("{name} exceeds the maximum file size");
+		}
+		total += data.len();
+		if total > max_total {
+			bail!("The files in the archive exceed the maximum total size");
+		}
+
+		match mimetype {
+			Some(mimetype) => images.push(Image {
+				name,
+				mimetype,
+				data
+			}),
+			None => {
+				manifest = serde_json::from_slice(&data)
+					.context("Failed to parse the manifest")?
+			},
+		}
+	}
+
+	images.sort_by(|a, b| a.name.cmp(&b.name));
+	if !manifest.stickers.is_empty() {
+		let mut ordered = Vec::new();
+		for name in manifest.stickers.keys() {
+			let idx = images
+				.iter()
+				.position(|image| &image.name == name)
+				.ok_or_else(|| anyhow!("The manifest lists {name}, but it is missing"))?;
+			ordered.push(images.swap_remove(idx));
+		}
+		images = ordered;
+	}
+	Ok((manifest, images))
+}
+
+/// Import a sticker pack from a zip archive of images that was sent to the room.
+pub(super) async fn import_archive(
+	room: &Room,
+	requester: &UserId,
+	file: &FileMessageEventContent,
+	on_collision: OnCollision
+) -> anyhow::Result<Vec<String>> {
+	let client = room.client();
+	let limits = &config().limits;
+	let data = download_file(&client, file, limits.max_file_size)
+		.await
+		.context("Failed to download the archive")?;
+	let (manifest, images) = read_archive(
+		data,
+		limits.max_file_size,
+		limits.max_stickers,
+		limits.max_archive_size
+	)?;
+	if images.is_empty() {
+		bail!("The archive doesn't contain any png, webp, gif or jpeg images");
+	}
+
+	let file_name = file.filename.as_deref().unwrap_or(&file.body);
+	let title = manifest.title.clone().unwrap_or_else(|| {
+		file_name
+			.rsplit_once('.')
+			.map_or(file_name, |(stem, _)| stem)
+			.to_owned()
+	});
+	let source = PackSource::new(SourceType::Archive, media_url(&file.source), requester);
+	let id =
+		resolve_state_key(room, &slugify(&title), &source, &title, on_collision).await?;
+
+	// database, stored in the matrix account data, to prevent duplicate file uploads
+	let db = AccountDataDatabase::load(&client)
+		.await
+		.context("Failed to load database from account data")?;
+
+	let usage = config()
+		.room(room.room_id())
+		.usage
+		.unwrap_or(PackUsage::Sticker);
+	let mut pack = ponies::StickerPack {
+		images: IndexMap::new(),
+		pack: ponies::PackInfo {
+			display_name: title,
+			avatar_url: None
+		}
+	};
+	for (i, image) in images.into_iter().enumerate() {
+		let entry = manifest.stickers.get(&image.name);
+		let mut shortcode = entry
+			.and_then(|entry| entry.shortcode.clone())
+			.unwrap_or_else(|| image.stem().to_snake_case());
+		if shortcode.is_empty() {
+			shortcode = format!("sticker{}", i + 1);
+		}
+		let body = entry
+			.and_then(|entry| entry.body.clone())
+			.unwrap_or_else(|| image.stem().to_owned());
+
+		let size = imagesize::blob_size(&image.data).with_context(|| {
+			format!("Failed to read the dimensions of {}", image.name)
+		})?;
+		let info = serde_json::from_value(json!({
+			"w": size.width,
+			"h": size.height,
+			"size": image.data.len(),
+			"mimetype": image.mimetype
+		}))?;
+		let url = db
+			.upload(&client, image.data, &image.mimetype.parse()?)
+			.await
+			.with_context(|| format!("Failed to upload {}", image.name))?;
+
+		let shortcode = unique_shortcode(&pack.images, &shortcode);
+		pack.images.insert(shortcode, ponies::Sticker {
+			body,
+			info,
+			url,
+			usage: usage.usage().into_iter().collect()
+		});
+	}
+	fill_avatar(&mut pack);
+
+	if let Err(err) = db.store(&client).await {
+		error!("Unable to store database to account data: {err:?}");
+	}
+
+	let pack = RoomStickerPack {
+		pack,
+		source: Some(source),
+		part: None
+	};
+	let keys = write_stickerpack(room, &id, pack)
+		.await
+		.context("Failed to add the sticker pack to the room")?;
+	Ok(split_note(&keys).into_iter().collect())
+}
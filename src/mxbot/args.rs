@@ -0,0 +1,48 @@
+use anyhow::bail;
+
+/// The arguments of a command, consisting of positional arguments and `--flags`.
+#[derive(Debug, Default)]
+pub(super) struct Args {
+	pub(super) positional: Vec<String>,
+	switches: Vec<String>,
+	options: Vec<(String, String)>
+}
+
+impl Args {
+	/// Parse the arguments. `switches` are flags without a value, `options` are flags
+	/// that are followed by a value. All other flags are rejected.
+	pub(super) fn parse(
+		input: &str,
+		switches: &[&str],
+		options: &[&str]
+	) -> anyhow::Result<Self> {
+		let mut args = Self::default();
+		let mut words = input.split_whitespace();
+		while let Some(word) = words.next() {
+			if !word.starts_with("--") {
+				args.positional.push(word.to_owned());
+			} else if switches.contains(&word) {
+				args.switches.push(word.to_owned());
+			} else if options.contains(&word) {
+				let Some(value) = words.next() else {
+					bail!("Missing value for {word}");
+				};
+				args.options.push((word.to_owned(), value.to_owned()));
+			} else {
+				bail!("Unknown flag {word}");
+			}
+		}
+		Ok(args)
+	}
+
+	pub(super) fn switch(&self, name: &str) -> bool {
+		self.switches.iter().any(|switch| switch == name)
+	}
+
+	pub(super) fn option(&self, name: &str) -> Option<&str> {
+		self.options
+			.iter()
+			.find(|(option, _)| option == name)
+			.map(|(_, value)| value.as_str())
+	}
+}
@@ -0,0 +1,178 @@
+use super::{
+	args::Args,
+	media::{download_file, replied_file},
+	power::check_can_write_packs,
+	state::{
+		read_all_stickerpacks, read_media_map, write_media_map, write_room_state,
+		MediaMap
+	}
+};
+use crate::config::config;
+use anyhow::{anyhow, bail, Context as _};
+use log::error;
+use matrix_sdk::{
+	attachment::AttachmentConfig,
+	room::Room,
+	ruma::{
+		events::room::message::RoomMessageEventContent, MilliSecondsSinceUnixEpoch,
+		OwnedRoomId, RoomId
+	},
+	Client, RoomState
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The version of the backup format. Increase this on incompatible changes.
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct BackupVersion {
+	version: u32
+}
+
+#[derive(Deserialize, Serialize)]
+struct Backup {
+	version: u32,
+	created_at: MilliSecondsSinceUnixEpoch,
+	bot_version: String,
+
+	/// The content of all `im.ponies.room_emotes` state events by room and state key.
+	rooms: BTreeMap<OwnedRoomId, BTreeMap<String, serde_json::Value>>,
+
+	media_map: MediaMap
+}
+
+/// Back up the sticker packs of all rooms we are in, and upload the backup as a file.
+pub(super) async fn backup(room: &Room) -> anyhow::Result<Vec<String>> {
+	// the backup contains the sticker packs of every room, including private ones
+	if room.is_public() || !room.is_encrypted().await? {
+		bail!("Backups can only be uploaded to private, encrypted rooms");
+	}
+
+	let client = room.client();
+	let mut rooms = BTreeMap::new();
+	for joined in client.joined_rooms() {
+		let packs = read_all_stickerpacks(&joined).await.with_context(|| {
+			format!("Failed to read the sticker packs of {}", joined.room_id())
+		})?;
+		if !packs.is_empty() {
+			rooms.insert(joined.room_id().to_owned(), packs);
+		}
+	}
+	let packs: usize = rooms.values().map(|packs| packs.len()).sum();
+	let room_count = rooms.len();
+
+	let backup = Backup {
+		version: BACKUP_VERSION,
+		created_at: MilliSecondsSinceUnixEpoch::now(),
+		bot_version: env!("CARGO_PKG_VERSION").to_owned(),
+		rooms,
+		media_map: read_media_map(&client).await?.unwrap_or_default()
+	};
+	room.send_attachment(
+		&format!("tg2mx_bot-backup-{}.json", backup.created_at.get()),
+		&mime::APPLICATION_JSON,
+		serde_json::to_vec(&backup)?,
+		AttachmentConfig::new()
+	)
+	.await
+	.context("Failed to upload the backup")?;
+
+	Ok(vec![format!(
+		"Backed up {packs} sticker packs from {room_count} rooms."
+	)])
+}
+
+fn parse_room_id(room_id: &str) -> anyhow::Result<OwnedRoomId> {
+	RoomId::parse(room_id).with_context(|| format!("Invalid room id {room_id}"))
+}
+
+/// Write the sticker packs into the room, returning the number of packs written.
+async fn restore_room(
+	client: &Client,
+	room_id: &RoomId,
+	packs: &BTreeMap<String, serde_json::Value>
+) -> anyhow::Result<usize> {
+	let room = client
+		.get_room(room_id)
+		.filter(|room| room.state() == RoomState::Joined)
+		.ok_or_else(|| anyhow!("I am not in the room {room_id}"))?;
+	check_can_write_packs(&room).await?;
+	for (state_key, content) in packs {
+		write_room_state(
+			&room,
+			"im.ponies.room_emotes",
+			Some(state_key.as_str()),
+			content
+		)
+		.await
+		.with_context(|| format!("Failed to restore the sticker pack {state_key}"))?;
+	}
+	Ok(packs.len())
+}
+
+/// Restore the sticker packs from the backup that the message replies to.
+pub(super) async fn restore(
+	room: &Room,
+	content: &RoomMessageEventContent,
+	args: &str
+) -> anyhow::Result<Vec<String>> {
+	let args = Args::parse(args, &[], &[])?;
+	let (only, target) = match args.positional.as_slice() {
+		[] => (None, None),
+		[room_id] => (Some(parse_room_id(room_id)?), None),
+		[room_id, target] => {
+			(Some(parse_room_id(room_id)?), Some(parse_room_id(target)?))
+		},
+		_ => bail!("Usage: !restore [<room> [<target room>]], as a reply to a backup")
+	};
+
+	let client = room.client();
+	let file = replied_file(room, content).await?;
+	let bytes = download_file(&client, &file, config().limits.max_file_size)
+		.await
+		.context("Failed to download the backup")?;
+	let BackupVersion { version } =
+		serde_json::from_slice(&bytes).context("The file is not a backup")?;
+	if version > BACKUP_VERSION {
+		bail!("The backup was created by a newer version of this bot");
+	}
+	let backup: Backup =
+		serde_json::from_slice(&bytes).context("Failed to parse the backup")?;
+	if let Some(only) = &only {
+		if !backup.rooms.contains_key(only) {
+			bail!("The backup does not contain any sticker packs of {only}");
+		}
+	}
+
+	let mut summary = Vec::new();
+	for (room_id, packs) in &backup.rooms {
+		if only.as_ref().is_some_and(|only| only != room_id) {
+			continue;
+		}
+		let target = target.as_deref().unwrap_or(room_id);
+		match restore_room(&client, target, packs).await {
+			Ok(count) => summary.push(format!(
+				"- {room_id}: Restored {count} sticker packs into {target}"
+			)),
+			Err(err) => {
+				error!("Failed to restore the sticker packs of {room_id}: {err:?}");
+				summary.push(format!("- {room_id}: Failed: {err:#}"));
+			}
+		}
+	}
+
+	// keep the urls of media that was uploaded after the backup was made
+	let mut media_map = read_media_map(&client).await?.unwrap_or_default();
+	for (hash, cache) in backup.media_map.map {
+		media_map.map.entry(hash).or_insert(cache);
+	}
+	write_media_map(&client, &media_map)
+		.await
+		.context("Failed to restore the media database")?;
+
+	Ok(vec![format!(
+		"Restored the backup:\n{}",
+		summary.join("\n")
+	)])
+}
@@ -0,0 +1,93 @@
+use super::{
+	args::Args,
+	power::{check_can_write_packs, may_manage_packs},
+	space::parent_space,
+	split_note,
+	state::{
+		fetch_split_stickerpack, fetch_stickerpack, remove_stickerpack,
+		write_stickerpack, PACK_LOCK
+	}
+};
+use anyhow::{anyhow, bail, Context as _};
+use matrix_sdk::{
+	room::Room,
+	ruma::{RoomAliasId, RoomId, UserId},
+	Client, RoomState
+};
+
+/// Find a room that we are in by its id or alias.
+pub(super) async fn joined_room(client: &Client, room: &str) -> anyhow::Result<Room> {
+	let room_id = if room.starts_with('#') {
+		let alias = RoomAliasId::parse(room)
+			.with_context(|| format!("Invalid room alias {room}"))?;
+		client
+			.resolve_room_alias(&alias)
+			.await
+			.with_context(|| format!("Failed to resolve the room alias {room}"))?
+			.room_id
+	} else {
+		RoomId::parse(room).with_context(|| format!("Invalid room id {room}"))?
+	};
+	client
+		.get_room(&room_id)
+		.filter(|room| room.state() == RoomState::Joined)
+		.ok_or_else(|| anyhow!("I am not in the room {room}, please invite me first"))
+}
+
+/// Copy a sticker pack into a different room, and remove it from this room if `remove`
+/// is set.
+pub(super) async fn copy_pack(
+	room: &Room,
+	requester: &UserId,
+	args: &str,
+	remove: bool
+) -> anyhow::Result<Vec<String>> {
+	let args = Args::parse(args, &["--space"], &["--as"])?;
+	let (id, target, target_name) =
+		match (args.positional.as_slice(), args.switch("--space")) {
+			([id], true) => (id, parent_space(room).await?, "the space".to_owned()),
+			([id, target_name], false) => {
+				let target = joined_room(&room.client(), target_name).await?;
+				(id, target, target_name.clone())
+			},
+			_ => {
+				let cmd = if remove { "!move" } else { "!copy" };
+				bail!(
+					"Usage: {cmd} <pack> <room> [--as <id>] or {cmd} <pack> --space \
+					 [--as <id>]"
+				);
+			}
+		};
+	let new_id = args.option("--as").unwrap_or(id);
+
+	if target.room_id() == room.room_id() && new_id == id.as_str() {
+		bail!("The sticker pack {id} is already in this room");
+	}
+	for (room, name) in [(room, "this room"), (&target, target_name.as_str())] {
+		if !may_manage_packs(room, requester).await? {
+			bail!("Your power level in {name} is too low to manage its sticker packs.");
+		}
+	}
+	check_can_write_packs(&target).await?;
+
+	let _guard = PACK_LOCK.lock().await;
+	let pack = fetch_split_stickerpack(room, id)
+		.await?
+		.ok_or_else(|| anyhow!("There is no sticker pack {id} in this room"))?;
+	if fetch_stickerpack(&target, new_id).await?.is_some() {
+		bail!(
+			"There already is a sticker pack {new_id} in {target_name}. Use --as to \
+			 choose a different id."
+		);
+	}
+	let keys = write_stickerpack(&target, new_id, pack)
+		.await
+		.with_context(|| format!("Failed to add the sticker pack to {target_name}"))?;
+
+	if remove {
+		remove_stickerpack(room, id)
+			.await
+			.context("Failed to remove the sticker pack from this room")?;
+	}
+	Ok(split_note(&keys).into_iter().collect())
+}
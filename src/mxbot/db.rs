@@ -1,6 +1,8 @@
 use super::state::{read_media_map, write_media_map, MediaCache, MediaHash, MediaMap};
 use matrix_sdk::Client;
+use mime::Mime;
 use mstickerlib::database::{self, Database};
+use sha2::{Digest as _, Sha512};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -23,6 +25,29 @@ impl AccountDataDatabase {
 		let map = self.map.read().await;
 		write_media_map(client, &map).await
 	}
+
+	/// Upload the media to the homeserver, unless it was uploaded before. Returns the
+	/// mxc url of the media.
+	pub(super) async fn upload(
+		&self,
+		client: &Client,
+		data: Vec<u8>,
+		content_type: &Mime
+	) -> anyhow::Result<String> {
+		let hash: database::Hash = Sha512::digest(&data).into();
+		if let Some(url) = self.get(&hash).await? {
+			return Ok(url);
+		}
+
+		let url = client
+			.media()
+			.upload(content_type, data)
+			.await?
+			.content_uri
+			.to_string();
+		self.add(hash, url.clone()).await?;
+		Ok(url)
+	}
 }
 
 //#[async_trait]
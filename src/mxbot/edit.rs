@@ -0,0 +1,229 @@
+use super::{
+	args::Args,
+	media::replied_event,
+	state::{
+		fetch_split_stickerpack, fill_avatar, write_stickerpack, PackSource,
+		RoomStickerPack, SourceType, PACK_LOCK
+	}
+};
+use crate::config::{config, PackUsage};
+use anyhow::{anyhow, bail};
+use indexmap::IndexMap;
+use matrix_sdk::{
+	room::Room,
+	ruma::{
+		events::{
+			room::{
+				message::{MessageType, RoomMessageEventContent},
+				MediaSource
+			},
+			AnyMessageLikeEvent, MessageLikeEvent
+		},
+		UserId
+	}
+};
+use mstickerlib::matrix::sticker_formats::ponies;
+
+/// Apply a change to a sticker pack, including all parts it was split into. If the pack
+/// doesn't exist, `default` is changed instead, or an error is returned if there is no
+/// default.
+async fn modify_stickerpack<F>(
+	room: &Room,
+	id: &str,
+	default: Option<RoomStickerPack>,
+	modify: F
+) -> anyhow::Result<()>
+where
+	F: FnOnce(&mut ponies::StickerPack) -> anyhow::Result<()>
+{
+	let _guard = PACK_LOCK.lock().await;
+	let mut pack = fetch_split_stickerpack(room, id)
+		.await?
+		.or(default)
+		.ok_or_else(|| anyhow!("There is no sticker pack {id} in this room"))?;
+	modify(&mut pack.pack)?;
+	write_stickerpack(room, id, pack).await?;
+	Ok(())
+}
+
+/// Return the index of the sticker in the pack.
+fn sticker_index(
+	pack: &ponies::StickerPack,
+	id: &str,
+	shortcode: &str
+) -> anyhow::Result<usize> {
+	pack.images.get_index_of(shortcode).ok_or_else(|| {
+		anyhow!("There is no sticker {shortcode} in the sticker pack {id}")
+	})
+}
+
+/// Use one of the stickers of the pack as the pack's avatar.
+pub(super) async fn set_avatar(room: &Room, args: &str) -> anyhow::Result<()> {
+	let args = Args::parse(args, &[], &[])?;
+	let [id, shortcode] = args.positional.as_slice() else {
+		bail!("Usage: !avatar <pack> <sticker>");
+	};
+
+	modify_stickerpack(room, id, None, |pack| {
+		let idx = sticker_index(pack, id, shortcode)?;
+		pack.pack.avatar_url = Some(pack.images[idx].url.clone());
+		Ok(())
+	})
+	.await
+}
+
+/// Add the image or sticker that the message replies to to a sticker pack, creating
+/// the pack if it doesn't exist yet.
+pub(super) async fn add_sticker(
+	room: &Room,
+	requester: &UserId,
+	content: &RoomMessageEventContent,
+	args: &str
+) -> anyhow::Result<()> {
+	let args = Args::parse(args, &[], &["--usage"])?;
+	let [id, shortcode] = args.positional.as_slice() else {
+		bail!(
+			"Usage: !add <pack> <shortcode> [--usage sticker|emoticon|both], as a reply \
+			 to an image or sticker"
+		);
+	};
+	let usage = match args.option("--usage") {
+		Some(usage) => usage.parse()?,
+		None => config()
+			.room(room.room_id())
+			.usage
+			.unwrap_or(PackUsage::Sticker)
+	};
+
+	let ev = replied_event(room, content)
+		.await?
+		.ok_or_else(|| anyhow!("Please reply to an image or sticker"))?;
+	let event_url = room.room_id().matrix_to_event_uri(ev.event_id().to_owned());
+	let (body, url, info) = match ev {
+		AnyMessageLikeEvent::Sticker(MessageLikeEvent::Original(ev)) => {
+			(ev.content.body, ev.content.url, ev.content.info)
+		},
+		AnyMessageLikeEvent::RoomMessage(MessageLikeEvent::Original(ev)) => {
+			let MessageType::Image(image) = ev.content.msgtype else {
+				bail!("The message you replied to is not an image");
+			};
+			let MediaSource::Plain(url) = image.source else {
+				bail!("Encrypted images can't be added to sticker packs");
+			};
+			// the body of an image is usually its file name
+			let info = image.info.map(|info| *info).unwrap_or_default();
+			(shortcode.clone(), url, info)
+		},
+		_ => bail!("The event you replied to is not an image or sticker")
+	};
+
+	let sticker = ponies::Sticker {
+		body,
+		info: serde_json::from_value(serde_json::to_value(info)?)?,
+		url: url.to_string(),
+		usage: usage.usage().into_iter().collect()
+	};
+	let new_pack = RoomStickerPack {
+		pack: ponies::StickerPack {
+			images: IndexMap::new(),
+			pack: ponies::PackInfo {
+				display_name: id.clone(),
+				avatar_url: None
+			}
+		},
+		source: Some(PackSource::new(
+			SourceType::Matrix,
+			event_url.to_string(),
+			requester
+		)),
+		part: None
+	};
+	modify_stickerpack(room, id, Some(new_pack), |pack| {
+		if pack.images.contains_key(shortcode) {
+			bail!("The sticker pack {id} already contains a sticker {shortcode}");
+		}
+		pack.images.insert(shortcode.clone(), sticker);
+		fill_avatar(pack);
+		Ok(())
+	})
+	.await
+}
+
+/// Edit a single sticker of a sticker pack.
+pub(super) async fn edit_pack(room: &Room, args: &str) -> anyhow::Result<()> {
+	let (subcommand, args) = args.split_once(' ').unwrap_or((args, ""));
+
+	// the body is free text, so only split off the pack and shortcode
+	if subcommand == "body" {
+		let mut words = args.trim_start().splitn(3, ' ');
+		let (Some(id), Some(shortcode), Some(text)) =
+			(words.next(), words.next(), words.next())
+		else {
+			bail!("Usage: !pack body <pack> <shortcode> <text>");
+		};
+		let text = text.trim();
+		return modify_stickerpack(room, id, None, |pack| {
+			let idx = sticker_index(pack, id, shortcode)?;
+			pack.images[idx].body = text.to_owned();
+			Ok(())
+		})
+		.await;
+	}
+
+	let args = Args::parse(args, &[], &[])?;
+	match (subcommand, args.positional.as_slice()) {
+		("rename", [id, old, new]) => {
+			modify_stickerpack(room, id, None, |pack| {
+				let idx = sticker_index(pack, id, old)?;
+				if pack.images.contains_key(new) {
+					bail!("The sticker pack {id} already contains a sticker {new}");
+				}
+				let sticker = pack.images.shift_remove_index(idx).unwrap().1;
+				pack.images.insert(new.clone(), sticker);
+				pack.images.move_index(pack.images.len() - 1, idx);
+				Ok(())
+			})
+			.await
+		},
+		("rm", [id, shortcode]) => {
+			modify_stickerpack(room, id, None, |pack| {
+				let idx = sticker_index(pack, id, shortcode)?;
+				let sticker = pack.images.shift_remove_index(idx).unwrap().1;
+				if pack.pack.avatar_url.as_ref() == Some(&sticker.url) {
+					pack.pack.avatar_url = None;
+					fill_avatar(pack);
+				}
+				Ok(())
+			})
+			.await
+		},
+		("move", [id, shortcode, position]) => {
+			let position: usize = position
+				.parse()
+				.map_err(|_| anyhow!("Invalid position {position}"))?;
+			modify_stickerpack(room, id, None, |pack| {
+				let idx = sticker_index(pack, id, shortcode)?;
+				if !(1 ..= pack.images.len()).contains(&position) {
+					bail!("The position must be between 1 and {}", pack.images.len());
+				}
+				pack.images.move_index(idx, position - 1);
+				Ok(())
+			})
+			.await
+		},
+		("usage", [id, shortcode, usage]) => {
+			let usage: PackUsage = usage.parse()?;
+			modify_stickerpack(room, id, None, |pack| {
+				let idx = sticker_index(pack, id, shortcode)?;
+				pack.images[idx].usage = usage.usage().into_iter().collect();
+				Ok(())
+			})
+			.await
+		},
+		_ => bail!(
+			"Usage: !pack rename <pack> <old> <new>, !pack body <pack> <shortcode> \
+			 <text>, !pack rm <pack> <shortcode>, !pack move <pack> <shortcode> \
+			 <position> or !pack usage <pack> <shortcode> sticker|emoticon|both"
+		)
+	}
+}
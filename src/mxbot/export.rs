@@ -0,0 +1,102 @@
+use super::{args::Args, state::read_split_stickerpack};
+use anyhow::{anyhow, bail, Context as _};
+use matrix_sdk::{attachment::AttachmentConfig, room::Room};
+use mstickerlib::matrix::sticker_formats::{maunium, ponies};
+use serde_json::{json, Value};
+
+/// The format a sticker pack is exported to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ExportFormat {
+	/// The format used by maunium's stickerpicker.
+	Maunium,
+	/// The `im.ponies.room_emotes` state event content.
+	Ponies
+}
+
+/// Convert the sticker pack to the format used by maunium's stickerpicker. The result
+/// is checked against [`maunium::StickerPack`], which is what `!migrate` reads.
+fn to_maunium(id: &str, pack: &ponies::StickerPack) -> anyhow::Result<Value> {
+	let stickers: Vec<_> = pack
+		.images
+		.iter()
+		.map(|(shortcode, sticker)| {
+			json!({
+				"body": sticker.body,
+				"url": sticker.url,
+				"info": sticker.info,
+				"msgtype": "m.sticker",
+				"id": shortcode
+			})
+		})
+		.collect();
+	let json = json!({
+		"title": pack.pack.display_name,
+		"id": id,
+		"stickers": stickers
+	});
+	serde_json::from_value::<maunium::StickerPack>(json.clone())
+		.context("Failed to convert the sticker pack to the maunium format")?;
+	Ok(json)
+}
+
+/// Export a sticker pack of the room and upload it as a file.
+pub(super) async fn export(room: &Room, args: &str) -> anyhow::Result<()> {
+	let args = Args::parse(args, &[], &["--format"])?;
+	let [id] = args.positional.as_slice() else {
+		bail!("Usage: !export <pack> [--format maunium|ponies]");
+	};
+	let format = match args.option("--format") {
+		None | Some("maunium") => ExportFormat::Maunium,
+		Some("ponies") => ExportFormat::Ponies,
+		Some(format) => bail!("Unknown format {format}, expected maunium or ponies")
+	};
+
+	let pack = read_split_stickerpack(room, id)
+		.await?
+		.ok_or_else(|| anyhow!("There is no sticker pack {id} in this room"))?
+		.pack;
+	let json = match format {
+		ExportFormat::Maunium => to_maunium(id, &pack)?,
+		ExportFormat::Ponies => serde_json::to_value(&pack)?
+	};
+
+	room.send_attachment(
+		&format!("{id}.json"),
+		&mime::APPLICATION_JSON,
+		serde_json::to_vec_pretty(&json)?,
+		AttachmentConfig::new()
+	)
+	.await
+	.context("Failed to upload the sticker pack")?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn maunium_export_can_be_migrated() {
+		let pack: ponies::StickerPack = serde_json::from_value(json!({
+			"images": {
+				"cat": {
+					"body": "A cat",
+					"url": "mxc://example.org/cat",
+					"info": {
+						"w": 256,
+						"h": 256,
+						"size": 1234,
+						"mimetype": "image/png"
+					},
+					"usage": ["sticker"]
+				}
+			},
+			"pack": {
+				"display_name": "Cats"
+			}
+		}))
+		.unwrap();
+		let json = to_maunium("cats", &pack).unwrap();
+		let _: maunium::StickerPack = serde_json::from_value(json).unwrap();
+	}
+}
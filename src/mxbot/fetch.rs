@@ -0,0 +1,359 @@
+use crate::config::config;
+use anyhow::{bail, Context as _};
+use reqwest::{
+	header::{ACCEPT, CONTENT_TYPE, LOCATION, USER_AGENT},
+	redirect::Policy,
+	Response, Url
+};
+use std::{
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	time::Duration
+};
+use tokio::{net::lookup_host, time::timeout};
+
+/// Read the body of the response, failing if it is larger than `max_len` bytes.
+pub(super) async fn read_limited(
+	mut response: Response,
+	max_len: usize
+) -> anyhow::Result<Vec<u8>> {
+	if response.content_length().unwrap_or(0) > max_len as u64 {
+		bail!("Maximum content length exceeded");
+	}
+
+	// the caller knows the url, and it might contain secrets like the telegram bot token
+	let mut bytes = Vec::new();
+	while let Some(chunk) = response
+		.chunk()
+		.await
+		.map_err(reqwest::Error::without_url)?
+	{
+		if bytes.len() + chunk.len() > max_len {
+			bail!("Maximum content length exceeded");
+		}
+		bytes.extend_from_slice(&chunk);
+	}
+	Ok(bytes)
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+	let [a, b, ..] = ip.octets();
+	!(ip.is_unspecified()
+		|| ip.is_loopback()
+		|| ip.is_private()
+		|| ip.is_link_local()
+		|| ip.is_broadcast()
+		|| ip.is_documentation()
+		|| ip.is_multicast()
+		// 0.0.0.0/8
+		|| a == 0
+		// 100.64.0.0/10 (shared address space)
+		|| (a == 100 && (b & 0xc0) == 64)
+		// 192.0.0.0/24 (protocol assignments)
+		|| ip.octets()[.. 3] == [192, 0, 0]
+		// 198.18.0.0/15 (benchmarking)
+		|| (a == 198 && (b & 0xfe) == 18)
+		// 240.0.0.0/4 (reserved)
+		|| a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+	if let Some(ipv4) = ip.to_ipv4_mapped() {
+		return is_public_ipv4(ipv4);
+	}
+	let first = ip.segments()[0];
+	!(ip.is_unspecified()
+		|| ip.is_loopback()
+		|| ip.is_multicast()
+		// fc00::/7 (unique local)
+		|| (first & 0xfe00) == 0xfc00
+		// fe80::/10 (link local)
+		|| (first & 0xffc0) == 0xfe80
+		// 2001:db8::/32 (documentation)
+		|| (first == 0x2001 && ip.segments()[1] == 0xdb8)
+		// 64:ff9b::/96 (nat64) and 2002::/16 (6to4) can embed private ipv4 addresses
+		|| ip.segments()[.. 6] == [0x64, 0xff9b, 0, 0, 0, 0]
+		|| first == 0x2002)
+}
+
+fn is_public(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => is_public_ipv4(ip),
+		IpAddr::V6(ip) => is_public_ipv6(ip)
+	}
+}
+
+/// Downloads user-supplied urls. Only http(s) urls are allowed, and unless the host is
+/// allowlisted, it must not resolve to a loopback, private or otherwise non-public
+/// address. Redirects are followed manually so that every hop is checked.
+pub(super) struct Fetcher<'a> {
+	/// Hosts that may be fetched even if they resolve to a non-public address.
+	pub(super) allowlist: &'a [String],
+	/// The accepted content types, without parameters.
+	pub(super) content_types: &'a [&'a str],
+	pub(super) max_len: usize,
+	pub(super) max_redirects: usize,
+	/// The timeout for the whole download, including all redirects.
+	pub(super) timeout: Duration
+}
+
+impl Fetcher<'static> {
+	/// A fetcher for json files, configured from the config file.
+	pub(super) fn json() -> Self {
+		let config = config();
+		Self {
+			allowlist: &config.fetch.allowlist,
+			content_types: &["application/json", "text/json", "text/plain"],
+			max_len: config.limits.max_content_length,
+			max_redirects: config.fetch.max_redirects,
+			timeout: Duration::from_secs(config.fetch.timeout_secs)
+		}
+	}
+}
+
+impl Fetcher<'_> {
+	pub(super) async fn get(&self, url: &Url) -> anyhow::Result<Vec<u8>> {
+		timeout(self.timeout, self.get_impl(url.clone()))
+			.await
+			.context("Timeout exceeded")?
+	}
+
+	/// Check the url and resolve its host, returning the addresses to connect to.
+	async fn check_url(&self, url: &Url) -> anyhow::Result<Vec<SocketAddr>> {
+		if !matches!(url.scheme(), "http" | "https") {
+			bail!("Unsupported url scheme {:?}", url.scheme());
+		}
+		let Some(host) = url.host_str() else {
+			bail!("The url has no host");
+		};
+		let port = url.port_or_known_default().unwrap_or(80);
+
+		// ipv6 hosts are enclosed in brackets
+		let host = host.trim_start_matches('[').trim_end_matches(']');
+		let addrs: Vec<SocketAddr> = lookup_host((host, port))
+			.await
+			.with_context(|| format!("Failed to resolve {host}"))?
+			.collect();
+		if addrs.is_empty() {
+			bail!("Failed to resolve {host}");
+		}
+
+		let allowlisted = self.allowlist.iter().any(|allowed| allowed == host);
+		if !allowlisted {
+			if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+				bail!(
+					"Refusing to connect to {host} as it resolves to {}",
+					addr.ip()
+				);
+			}
+		}
+		Ok(addrs)
+	}
+
+	async fn get_impl(&self, mut url: Url) -> anyhow::Result<Vec<u8>> {
+		for _ in 0 ..= self.max_redirects {
+			let addrs = self.check_url(&url).await?;
+
+			// connect to the addresses we checked, not to whatever the host resolves
+			// to the next time
+			// a proxy would resolve the host again, so never use one
+			let mut client = reqwest::Client::builder()
+				.redirect(Policy::none())
+				.no_proxy();
+			if let Some(domain) = url.domain() {
+				client = client.resolve_to_addrs(domain, &addrs);
+			}
+			let response = client
+				.build()?
+				.get(url.clone())
+				.header(ACCEPT, self.content_types.join(", "))
+				.header(USER_AGENT, "tg2mx_bot")
+				.send()
+				.await?;
+
+			if response.status().is_redirection() {
+				let Some(location) = response.headers().get(LOCATION) else {
+					bail!("Redirect without a location");
+				};
+				url = url
+					.join(location.to_str()?)
+					.context("Invalid redirect location")?;
+				continue;
+			}
+
+			let response = response.error_for_status()?;
+			let content_type = response
+				.headers()
+				.get(CONTENT_TYPE)
+				.and_then(|content_type| content_type.to_str().ok())
+				.and_then(|content_type| content_type.split(';').next())
+				.map(|content_type| content_type.trim().to_ascii_lowercase())
+				.unwrap_or_default();
+			if !self.content_types.contains(&content_type.as_str()) {
+				bail!("Unexpected content type {content_type:?}");
+			}
+
+			return read_limited(response, self.max_len).await;
+		}
+		bail!("Too many redirects")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::{
+		io::{AsyncReadExt as _, AsyncWriteExt as _},
+		net::TcpListener
+	};
+
+	#[test]
+	fn ipv4_classifier() {
+		for ip in ["1.1.1.1", "8.8.8.8", "100.128.0.1", "198.20.0.1"] {
+			assert!(is_public_ipv4(ip.parse().unwrap()), "{ip} is public");
+		}
+		for ip in [
+			"0.0.0.0",
+			"0.1.2.3",
+			"127.0.0.1",
+			"10.0.0.1",
+			"172.16.0.1",
+			"192.168.1.1",
+			"169.254.169.254",
+			"100.64.0.1",
+			"192.0.0.8",
+			"198.18.0.1",
+			"224.0.0.1",
+			"240.0.0.1",
+			"255.255.255.255"
+		] {
+			assert!(!is_public_ipv4(ip.parse().unwrap()), "{ip} is not public");
+		}
+	}
+
+	#[test]
+	fn ipv6_classifier() {
+		for ip in ["2606:4700::1111", "2a00:1450:4001::1"] {
+			assert!(is_public_ipv6(ip.parse().unwrap()), "{ip} is public");
+		}
+		for ip in [
+			"::",
+			"::1",
+			"fc00::1",
+			"fd12:3456::1",
+			"fe80::1",
+			"ff02::1",
+			"2001:db8::1",
+			"::ffff:127.0.0.1",
+			"::ffff:10.0.0.1",
+			"64:ff9b::a00:1",
+			"2002:a00:1::1"
+		] {
+			assert!(!is_public_ipv6(ip.parse().unwrap()), "{ip} is not public");
+		}
+	}
+
+	/// Start a server on localhost that answers every request with the response
+	/// returned by `respond`, and return its port.
+	async fn serve<F>(respond: F) -> u16
+	where
+		F: Fn(u16) -> String + Send + 'static
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let mut request = Vec::new();
+				let mut buf = [0; 1024];
+				while !request.ends_with(b"\r\n\r\n") {
+					match stream.read(&mut buf).await {
+						Ok(0) | Err(_) => break,
+						Ok(len) => request.extend_from_slice(&buf[.. len])
+					}
+				}
+				stream.write_all(respond(port).as_bytes()).await.ok();
+				stream.shutdown().await.ok();
+			}
+		});
+		port
+	}
+
+	fn response(status: &str, headers: &str, body: &str) -> String {
+		format!(
+			"HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: \
+			 close\r\n\r\n{body}",
+			body.len()
+		)
+	}
+
+	async fn fetch(port: u16) -> anyhow::Result<Vec<u8>> {
+		let allowlist = ["127.0.0.1".to_owned()];
+		let fetcher = Fetcher {
+			allowlist: &allowlist,
+			content_types: &["application/json"],
+			max_len: 1024,
+			max_redirects: 2,
+			timeout: Duration::from_secs(1)
+		};
+		let url = format!("http://127.0.0.1:{port}/index.json").parse()?;
+		fetcher.get(&url).await
+	}
+
+	#[tokio::test]
+	async fn allowlisted_host() {
+		let port =
+			serve(|_| response("200 OK", "Content-Type: application/json\r\n", "{}"))
+				.await;
+		assert_eq!(fetch(port).await.unwrap(), b"{}");
+	}
+
+	#[tokio::test]
+	async fn redirect_to_private_address() {
+		let port = serve(|_| {
+			response("302 Found", "Location: http://127.0.0.2/index.json\r\n", "")
+		})
+		.await;
+		let err = fetch(port).await.unwrap_err();
+		assert!(
+			format!("{err:#}").contains("Refusing to connect"),
+			"{err:#}"
+		);
+	}
+
+	#[tokio::test]
+	async fn wrong_content_type() {
+		let port =
+			serve(|_| response("200 OK", "Content-Type: text/html\r\n", "<html></html>"))
+				.await;
+		let err = fetch(port).await.unwrap_err();
+		assert!(
+			format!("{err:#}").contains("Unexpected content type"),
+			"{err:#}"
+		);
+	}
+
+	#[tokio::test]
+	async fn too_many_redirects() {
+		let port = serve(|port| {
+			let location = format!("Location: http://127.0.0.1:{port}/loop\r\n");
+			response("302 Found", &location, "")
+		})
+		.await;
+		let err = fetch(port).await.unwrap_err();
+		assert!(format!("{err:#}").contains("Too many redirects"), "{err:#}");
+	}
+
+	#[tokio::test]
+	async fn timeout_exceeded() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		// accept the connection, but never respond
+		let server = tokio::spawn(async move {
+			let (_stream, _) = listener.accept().await.unwrap();
+			tokio::time::sleep(Duration::from_secs(10)).await;
+		});
+		let err = fetch(port).await.unwrap_err();
+		assert!(format!("{err:#}").contains("Timeout exceeded"), "{err:#}");
+		server.abort();
+	}
+}
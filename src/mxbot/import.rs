@@ -1,25 +1,211 @@
 use crate::{
-	mxbot::{db::AccountDataDatabase, state::write_room_state},
-	TG_BOT_TOKEN
+	config::{config, PackUsage},
+	mxbot::{
+		archive::import_archive,
+		args::Args,
+		db::AccountDataDatabase,
+		fetch::read_limited,
+		media::{is_reply, replied_file},
+		shortcode::ShortcodeStyle,
+		slug::{resolve_state_key, slugify, OnCollision},
+		space::pack_room,
+		split_note,
+		state::{
+			fill_avatar, write_stickerpack, PackSource, RoomStickerPack, SourceType
+		}
+	}
 };
-use anyhow::{anyhow, Context as _};
-use heck::ToSnakeCase;
+use anyhow::{anyhow, bail, Context as _};
 use log::{error, warn};
-use matrix_sdk::room::Room;
+use matrix_sdk::{room::Room, ruma::events::room::message::OriginalRoomMessageEvent};
+use mime::Mime;
 use mstickerlib::{
+	get_client,
 	image::AnimationFormat,
 	matrix::{self, sticker_formats::ponies},
 	tg::{self, ImportConfig}
 };
+use serde::{de::DeserializeOwned, Deserialize};
+use std::fmt::Display;
+
+pub(super) struct ImportArgs {
+	/// The telegram sticker pack, or `None` if the pack is the archive the command
+	/// replied to.
+	pack: Option<String>,
+	on_collision: OnCollision,
+	pub(super) space: bool,
+	shortcodes: ShortcodeStyle
+}
+
+impl ImportArgs {
+	pub(super) fn parse(input: &str, is_reply: bool) -> anyhow::Result<Self> {
+		let [overwrite, rename] = OnCollision::SWITCHES;
+		let args =
+			Args::parse(input, &[overwrite, rename, "--space"], &["--shortcodes"])?;
+		let pack = match args.positional.as_slice() {
+			[pack] => Some(pack.clone()),
+			[] if is_reply => None,
+			_ => bail!(
+				"Usage: !import <pack> [--overwrite | --rename] [--space] [--shortcodes \
+				 index|emoji|name], or reply to a zip archive with !import"
+			)
+		};
+		let shortcodes = match args.option("--shortcodes") {
+			Some(_) if pack.is_none() => {
+				bail!("--shortcodes can only be used with telegram sticker packs")
+			},
+			Some(style) => style.parse()?,
+			None => ShortcodeStyle::default()
+		};
+		Ok(Self {
+			pack,
+			on_collision: OnCollision::from_args(&args)?,
+			space: args.switch("--space"),
+			shortcodes
+		})
+	}
+}
+
+#[derive(Deserialize)]
+struct TgResponse<T> {
+	result: Option<T>,
+	description: Option<String>
+}
+
+#[derive(Deserialize)]
+struct TgStickerSet {
+	/// Either `regular`, `mask` or `custom_emoji`.
+	#[serde(default)]
+	sticker_type: String,
+	stickers: Vec<TgSticker>,
+	#[serde(alias = "thumb")]
+	thumbnail: Option<TgFile>
+}
+
+#[derive(Deserialize)]
+struct TgSticker {
+	emoji: Option<String>
+}
+
+#[derive(Deserialize)]
+struct TgFile {
+	file_id: String,
+	file_path: Option<String>
+}
+
+/// Remove the bot token from an error, as errors of requests to telegram might include
+/// the url, which contains the token.
+fn redact_token(err: impl Display) -> anyhow::Error {
+	let message = format!("{err:#}").replace(config().tg_bot_token(), "<token>");
+	anyhow!(message)
+}
+
+async fn tg_request<T>(method: &str, query: &[(&str, &str)]) -> anyhow::Result<T>
+where
+	T: DeserializeOwned
+{
+	// the url contains the bot token, so it must not end up in error messages
+	let response: TgResponse<T> = get_client()
+		.get(format!(
+			"https://api.telegram.org/bot{}/{method}",
+			config().tg_bot_token()
+		))
+		.query(query)
+		.send()
+		.await
+		.map_err(reqwest::Error::without_url)?
+		.json()
+		.await
+		.map_err(reqwest::Error::without_url)?;
+	response.result.ok_or_else(|| {
+		anyhow!(
+			"Telegram request {method} failed: {}",
+			response.description.as_deref().unwrap_or("Unknown error")
+		)
+	})
+}
+
+/// The name of the telegram set, and whether it was linked as a custom emoji set.
+fn tg_set_name(pack: &str) -> anyhow::Result<(&str, bool)> {
+	let emoji_name = [
+		"https://t.me/addemoji/",
+		"http://t.me/addemoji/",
+		"t.me/addemoji/"
+	]
+	.into_iter()
+	.find_map(|prefix| pack.strip_prefix(prefix));
+	match emoji_name {
+		Some(name) => Ok((name.trim_end_matches('/'), true)),
+		None => Ok((
+			tg::pack_url_to_name(pack).context("Invalid sticker pack url")?,
+			false
+		))
+	}
+}
 
-pub(super) async fn import(room: &Room, pack: &str) -> anyhow::Result<()> {
-	let pack = tg::pack_url_to_name(pack).context("Invalid sticker pack url")?;
-	let mut id = pack.to_snake_case();
-	id.retain(|ch| ch.is_alphanumeric());
+/// Download the thumbnail of the sticker set, if it has one that matrix clients can
+/// display.
+async fn tg_thumbnail(set: &TgStickerSet) -> anyhow::Result<Option<(Vec<u8>, Mime)>> {
+	let Some(thumbnail) = &set.thumbnail else {
+		return Ok(None);
+	};
+	let file: TgFile = tg_request("getFile", &[("file_id", &thumbnail.file_id)]).await?;
+	let Some(path) = file.file_path else {
+		return Ok(None);
+	};
+
+	// animated thumbnails (tgs and webm) can't be used as an avatar
+	let content_type = match path.rsplit_once('.').map(|(_, ext)| ext) {
+		Some("webp") => "image/webp",
+		Some("png") => "image/png",
+		Some("jpg" | "jpeg") => "image/jpeg",
+		_ => return Ok(None)
+	};
+
+	let response = get_client()
+		.get(format!(
+			"https://api.telegram.org/file/bot{}/{path}",
+			config().tg_bot_token()
+		))
+		.send()
+		.await
+		.and_then(|response| response.error_for_status())
+		.map_err(reqwest::Error::without_url)?;
+	let data = read_limited(response, config().limits.max_file_size).await?;
+	Ok(Some((data, content_type.parse()?)))
+}
+
+pub(super) async fn import(
+	room: &Room,
+	ev: &OriginalRoomMessageEvent,
+	args: &str
+) -> anyhow::Result<Vec<String>> {
+	let args = ImportArgs::parse(args, is_reply(&ev.content))?;
+	let target = pack_room(room, &ev.sender, args.space).await?;
+	let Some(pack) = &args.pack else {
+		let file = replied_file(room, &ev.content).await?;
+		return import_archive(&target, &ev.sender, &file, args.on_collision).await;
+	};
+	let room = &target;
+	let (pack, emoji_link) = tg_set_name(pack)?;
+
+	// custom emoji sets are imported as emoticons that can be used inline
+	let set: TgStickerSet = tg_request("getStickerSet", &[("name", pack)])
+		.await
+		.context("Failed to load the sticker pack from telegram")?;
+	let is_emoji = emoji_link || set.sticker_type == "custom_emoji";
+	let source = PackSource::new(
+		SourceType::Telegram,
+		match is_emoji {
+			true => format!("https://t.me/addemoji/{pack}"),
+			false => format!("https://t.me/addstickers/{pack}")
+		},
+		&ev.sender
+	);
 
 	// config to connect to telegram
 	let tg_config = tg::Config {
-		bot_key: TG_BOT_TOKEN.as_deref().unwrap().to_owned()
+		bot_key: config().tg_bot_token().to_owned()
 	};
 
 	// config to connect to matrix
@@ -43,30 +229,92 @@ pub(super) async fn import(room: &Room, pack: &str) -> anyhow::Result<()> {
 	// load the telegram sticker pack
 	let sticker_pack = tg::StickerPack::get(pack, &tg_config)
 		.await
+		.map_err(redact_token)
 		.context("Failed to load the sticker pack from telegram")?;
 
+	// find the state key before uploading anything
+	let id = resolve_state_key(
+		room,
+		&slugify(pack),
+		&source,
+		&sticker_pack.title,
+		args.on_collision
+	)
+	.await?;
+
 	// import the pack to matrix
 	let mut import_config = ImportConfig::default();
 	import_config.animation_format = AnimationFormat::Webp;
 	import_config.database = Some(&db);
-	let matrix_pack = match sticker_pack
+	let (matrix_pack, failed) = match sticker_pack
 		.import(&tg_config, &matrix_config, &import_config)
 		.await
 	{
-		Ok(matrix_pack) => matrix_pack,
+		Ok(matrix_pack) => (matrix_pack, Vec::new()),
 		Err((matrix_pack, errors)) => {
 			// print warnings for those stickers from the set that were ignored
 			// TODO these warnings should be printed to matrix
+			let mut failed = Vec::new();
 			for (i, err) in errors {
 				warn!("Failed to import sticker {i}: {err:?}");
+				failed.push(i);
 			}
 
-			matrix_pack
+			(matrix_pack, failed)
+		}
+	};
+
+	// derive the shortcodes from the emoji of the stickers
+	let mut ponies: ponies::StickerPack = matrix_pack.into();
+	let emojis: Vec<_> = set
+		.stickers
+		.iter()
+		.enumerate()
+		.filter(|(i, _)| !failed.contains(i))
+		.map(|(_, sticker)| sticker.emoji.clone())
+		.collect();
+	if emojis.len() == ponies.images.len() {
+		// custom emoji are used inline, so their body should be the emoji they stand for
+		if is_emoji {
+			for (sticker, emoji) in ponies.images.values_mut().zip(&emojis) {
+				if let Some(emoji) = emoji {
+					sticker.body = emoji.clone();
+				}
+			}
 		}
+		ponies.images = args.shortcodes.apply(ponies.images, &emojis);
+	} else {
+		warn!("The emoji of the telegram set {pack} don't match its stickers");
+	}
+	let usage = match is_emoji {
+		true => Some(PackUsage::Emoticon),
+		false => config().room(room.room_id()).usage
 	};
+	if let Some(usage) = usage {
+		for sticker in ponies.images.values_mut() {
+			sticker.usage = usage.usage().into_iter().collect();
+		}
+	}
+
+	// use the thumbnail of the sticker set as the avatar, or the first sticker
+	match tg_thumbnail(&set).await {
+		Ok(Some((data, content_type))) => {
+			match db.upload(&client, data, &content_type).await {
+				Ok(url) => ponies.pack.avatar_url = Some(url),
+				Err(err) => warn!("Failed to upload the sticker set thumbnail: {err:?}")
+			}
+		},
+		Ok(None) => {},
+		Err(err) => warn!("Failed to load the sticker set thumbnail: {err:?}")
+	}
+	fill_avatar(&mut ponies);
 
-	let ponies: ponies::StickerPack = matrix_pack.into();
-	write_room_state(room, "im.ponies.room_emotes", Some(&id), ponies)
+	let room_pack = RoomStickerPack {
+		pack: ponies,
+		source: Some(source),
+		part: None
+	};
+	let keys = write_stickerpack(room, &id, room_pack)
 		.await
 		.context("Failed to add the sticker pack to the room")?;
 
@@ -76,5 +324,5 @@ pub(super) async fn import(room: &Room, pack: &str) -> anyhow::Result<()> {
 		error!("Unable to store database to account data: {err:?}");
 	}
 
-	Ok(())
+	Ok(split_note(&keys).into_iter().collect())
 }
@@ -0,0 +1,146 @@
+use super::fetch::read_limited;
+use anyhow::{anyhow, bail, Context as _};
+use matrix_sdk::{
+	crypto::AttachmentDecryptor,
+	room::Room,
+	ruma::events::{
+		room::{
+			message::{
+				FileMessageEventContent, MessageType, Relation, RoomMessageEventContent
+			},
+			MediaSource
+		},
+		AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent
+	},
+	Client
+};
+use mstickerlib::get_client;
+use std::io::{Cursor, Read as _};
+
+/// Remove the reply fallback from the plain text body of a message, so that commands
+/// can be sent as replies.
+pub(super) fn strip_reply_fallback(body: &str) -> &str {
+	if !body.starts_with('>') {
+		return body;
+	}
+	match body.split_once("\n\n") {
+		Some((fallback, rest))
+			if fallback
+				.lines()
+				.all(|line| line == ">" || line.starts_with("> ")) =>
+		{
+			rest
+		},
+		_ => body
+	}
+}
+
+/// Check whether the message is a reply to another event.
+pub(super) fn is_reply(content: &RoomMessageEventContent) -> bool {
+	matches!(content.relates_to, Some(Relation::Reply { .. }))
+}
+
+/// Return the event that the message replies to, if any.
+pub(super) async fn replied_event(
+	room: &Room,
+	content: &RoomMessageEventContent
+) -> anyhow::Result<Option<AnyMessageLikeEvent>> {
+	let Some(Relation::Reply { in_reply_to }) = &content.relates_to else {
+		return Ok(None);
+	};
+	let ev = room
+		.event(&in_reply_to.event_id)
+		.await
+		.context("Failed to fetch the event you replied to")?;
+	match ev.event.deserialize()? {
+		AnyTimelineEvent::MessageLike(ev) => Ok(Some(ev)),
+		AnyTimelineEvent::State(_) => bail!("The event you replied to is not a message")
+	}
+}
+
+/// Return the file that the message replies to.
+pub(super) async fn replied_file(
+	room: &Room,
+	content: &RoomMessageEventContent
+) -> anyhow::Result<FileMessageEventContent> {
+	let ev = replied_event(room, content)
+		.await?
+		.ok_or_else(|| anyhow!("Please reply to a file"))?;
+	match ev {
+		AnyMessageLikeEvent::RoomMessage(MessageLikeEvent::Original(ev)) => {
+			match ev.content.msgtype {
+				MessageType::File(file) => Ok(file),
+				_ => bail!("The message you replied to is not a file")
+			}
+		},
+		_ => bail!("The event you replied to is not a file")
+	}
+}
+
+/// The url of the media, which is the encrypted file's url for encrypted media.
+pub(super) fn media_url(source: &MediaSource) -> String {
+	match source {
+		MediaSource::Plain(url) => url.to_string(),
+		MediaSource::Encrypted(file) => file.url.to_string()
+	}
+}
+
+/// Download the media through our homeserver, aborting as soon as it exceeds `max_len`
+/// bytes.
+pub(super) async fn download_media(
+	client: &Client,
+	source: &MediaSource,
+	max_len: usize
+) -> anyhow::Result<Vec<u8>> {
+	let mxc = match source {
+		MediaSource::Plain(url) => url,
+		MediaSource::Encrypted(file) => &file.url
+	};
+	let (server, media_id) = mxc.parts().context("Invalid mxc url")?;
+	let mut url = client.homeserver();
+	url.path_segments_mut()
+		.map_err(|_| anyhow!("Invalid homeserver url"))?
+		.pop_if_empty()
+		.extend([
+			"_matrix",
+			"media",
+			"v3",
+			"download",
+			server.as_str(),
+			media_id
+		]);
+
+	// the sdk would buffer the whole file, so stream it ourselves
+	let mut request = get_client().get(url);
+	if let Some(access_token) = client.access_token() {
+		request = request.bearer_auth(access_token);
+	}
+	let response = request.send().await?.error_for_status()?;
+	let data = read_limited(response, max_len).await?;
+
+	match source {
+		MediaSource::Plain(_) => Ok(data),
+		MediaSource::Encrypted(file) => {
+			let mut cursor = Cursor::new(data);
+			let mut decryptor =
+				AttachmentDecryptor::new(&mut cursor, file.as_ref().clone().into())?;
+			let mut decrypted = Vec::new();
+			decryptor.read_to_end(&mut decrypted)?;
+			Ok(decrypted)
+		}
+	}
+}
+
+/// Download a file that was sent to a room, failing if it is larger than `max_len`
+/// bytes. Files that announce a larger size are not downloaded at all.
+pub(super) async fn download_file(
+	client: &Client,
+	file: &FileMessageEventContent,
+	max_len: usize
+) -> anyhow::Result<Vec<u8>> {
+	let size = file.info.as_ref().and_then(|info| info.size);
+	if size.is_some_and(|size| u64::from(size) > max_len as u64) {
+		bail!("Maximum content length exceeded");
+	}
+	download_media(client, &file.source, max_len).await
+}
@@ -1,51 +1,288 @@
-use super::state::read_stickerpack;
-use crate::mxbot::state::write_room_state;
+use super::{
+	args::Args,
+	db::AccountDataDatabase,
+	fetch::Fetcher,
+	media::{download_file, download_media, is_reply, media_url, replied_file},
+	rehost::rehost_stickers,
+	slug::{resolve_state_key, slugify, OnCollision},
+	space::pack_room,
+	split_note,
+	state::{
+		fill_avatar, merge_stickers, read_split_stickerpack, PackSource, RoomStickerPack,
+		SourceType
+	},
+	JobOutcome
+};
+use crate::{
+	config::{config, PackUsage},
+	mxbot::state::write_stickerpack
+};
 use anyhow::{bail, Context as _};
-use heck::ToSnakeCase;
 use indexmap::IndexMap;
-use log::info;
-use matrix_sdk::room::Room;
+use log::{error, info};
+use matrix_sdk::{
+	room::Room,
+	ruma::{
+		events::room::{message::OriginalRoomMessageEvent, MediaSource},
+		OwnedMxcUri, UserId
+	}
+};
 use mstickerlib::matrix::sticker_formats::{maunium, ponies};
-use reqwest::header::{ACCEPT, USER_AGENT};
+use reqwest::Url;
+use serde::Deserialize;
 
-const MAX_CONTENT_LENGTH: usize = 100 * 1024;
+/// What to do if the pack has already been migrated before.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OnExisting {
+	Skip,
+	Force,
+	Merge
+}
 
-pub(super) async fn migrate(room: &Room, pack: &str) -> anyhow::Result<()> {
-	let mut response = reqwest::Client::new()
-		.get(pack)
-		.header(ACCEPT, "application/json")
-		.header(USER_AGENT, "tg2mx_bot")
-		.send()
-		.await
-		.context("Failed to download maunium sticker pack")?;
+pub(super) struct MigrateArgs {
+	/// The url of the pack, or `None` if the pack is the file the command replied to.
+	url: Option<String>,
+	on_collision: OnCollision,
+	on_existing: OnExisting,
+	rehost: bool,
+	pub(super) space: bool
+}
 
-	if response.content_length().unwrap_or(0) > MAX_CONTENT_LENGTH as u64 {
-		bail!("Maximum content length exceeded");
+impl MigrateArgs {
+	pub(super) fn parse(input: &str, is_reply: bool) -> anyhow::Result<Self> {
+		let [overwrite, rename] = OnCollision::SWITCHES;
+		let args = Args::parse(
+			input,
+			&[
+				overwrite, rename, "--force", "--merge", "--rehost", "--space"
+			],
+			&[]
+		)?;
+		let url = match args.positional.as_slice() {
+			[url] => Some(url.clone()),
+			[] if is_reply => None,
+			_ => bail!(
+				"Usage: !migrate <url> [--force | --merge] [--overwrite | --rename] \
+				 [--rehost] [--space], or reply to a json file with !migrate"
+			)
+		};
+		let on_existing = match (args.switch("--force"), args.switch("--merge")) {
+			(false, false) => OnExisting::Skip,
+			(true, false) => OnExisting::Force,
+			(false, true) => OnExisting::Merge,
+			(true, true) => bail!("--force and --merge cannot be used together")
+		};
+		Ok(Self {
+			url,
+			on_collision: OnCollision::from_args(&args)?,
+			on_existing,
+			rehost: args.switch("--rehost"),
+			space: args.switch("--space")
+		})
 	}
+}
+
+/// The index file of a maunium stickerpicker deployment.
+#[derive(Deserialize)]
+struct StickerpickerIndex {
+	packs: Vec<String>
+}
+
+async fn download_json(url: &Url) -> anyhow::Result<Vec<u8>> {
+	Fetcher::json().get(url).await
+}
+
+/// The json file that a web url points to.
+enum WebPack {
+	Pack(maunium::StickerPack),
+	Index(StickerpickerIndex)
+}
 
-	let mut bytes = Vec::new();
-	while let Some(chunk) = response
-		.chunk()
+async fn download_web_pack(url: &Url) -> anyhow::Result<WebPack> {
+	let bytes = download_json(url)
 		.await
-		.context("Failed to download maunium sticker pack")?
-	{
-		if bytes.len() + chunk.len() > MAX_CONTENT_LENGTH {
-			bail!("Maximum content length exceeded");
+		.context("Failed to download maunium sticker pack")?;
+	if let Ok(index) = serde_json::from_slice(&bytes) {
+		return Ok(WebPack::Index(index));
+	}
+	let maunium_pack =
+		serde_json::from_slice(&bytes).context("Failed to parse maunium sticker pack")?;
+	Ok(WebPack::Pack(maunium_pack))
+}
+
+pub(super) async fn migrate(
+	room: &Room,
+	ev: &OriginalRoomMessageEvent,
+	args: &str
+) -> anyhow::Result<JobOutcome> {
+	let args = MigrateArgs::parse(args, is_reply(&ev.content))?;
+	let max_len = config().limits.max_content_length;
+	let client = room.client();
+	let target = pack_room(room, &ev.sender, args.space).await?;
+
+	// packs stored on matrix are downloaded through our homeserver
+	let (url, bytes) = match &args.url {
+		Some(url) if url.starts_with("mxc://") => {
+			let mxc = OwnedMxcUri::from(url.as_str());
+			if !mxc.is_valid() {
+				bail!("Invalid mxc url");
+			}
+			let bytes = download_media(&client, &MediaSource::Plain(mxc), max_len)
+				.await
+				.context("Failed to download maunium sticker pack")?;
+			(url.clone(), bytes)
+		},
+		Some(url) => return migrate_web(&target, &ev.sender, &args, url).await,
+		None => {
+			let file = replied_file(room, &ev.content).await?;
+			let bytes = download_file(&client, &file, max_len)
+				.await
+				.context("Failed to download maunium sticker pack")?;
+			(media_url(&file.source), bytes)
 		}
-		bytes.extend_from_slice(&chunk);
+	};
+
+	if serde_json::from_slice::<StickerpickerIndex>(&bytes).is_ok() {
+		bail!(
+			"Stickerpicker indexes can only be migrated from a web url, as the sticker \
+			 packs they list are relative to it"
+		);
 	}
-	let maunium_pack: maunium::StickerPack =
+	let maunium_pack =
 		serde_json::from_slice(&bytes).context("Failed to parse maunium sticker pack")?;
-	let mut id = maunium_pack.id.to_snake_case();
-	id.retain(|ch| ch.is_alphanumeric());
+	migrate_pack(&target, &ev.sender, &args, &url, maunium_pack).await
+}
+
+async fn migrate_web(
+	room: &Room,
+	requester: &UserId,
+	args: &MigrateArgs,
+	url: &str
+) -> anyhow::Result<JobOutcome> {
+	let mut url: Url = url.parse().context("Invalid url")?;
 
-	if read_stickerpack(room, &id)
+	// if the url doesn't point to a sticker pack or index, it might be the base url of
+	// a stickerpicker deployment
+	let web_pack = match download_web_pack(&url).await {
+		Ok(web_pack) => web_pack,
+		Err(err) => {
+			let mut index_url = url.clone();
+			if !index_url.path().ends_with('/') {
+				index_url.set_path(&format!("{}/", index_url.path()));
+			}
+			let index_url = index_url.join("packs/index.json")?;
+			match download_web_pack(&index_url).await {
+				Ok(WebPack::Index(index)) => {
+					url = index_url;
+					WebPack::Index(index)
+				},
+				// report why the url itself couldn't be migrated
+				_ => return Err(err)
+			}
+		}
+	};
+	let index = match web_pack {
+		WebPack::Pack(maunium_pack) => {
+			return migrate_pack(room, requester, args, url.as_str(), maunium_pack).await;
+		},
+		WebPack::Index(index) => index
+	};
+
+	let max_packs = config().limits.max_index_packs;
+	if index.packs.len() > max_packs {
+		bail!(
+			"The stickerpicker index lists {} sticker packs, but at most {max_packs} are \
+			 allowed",
+			index.packs.len()
+		);
+	}
+
+	let mut summary = Vec::new();
+	let mut migrated = 0;
+	let mut written = false;
+	for pack in &index.packs {
+		let res = async {
+			let pack_url = url.join(pack).context("Invalid sticker pack path")?;
+			let bytes = download_json(&pack_url)
+				.await
+				.context("Failed to download maunium sticker pack")?;
+			let maunium_pack = serde_json::from_slice(&bytes)
+				.context("Failed to parse maunium sticker pack")?;
+			migrate_pack(room, requester, args, pack_url.as_str(), maunium_pack).await
+		}
+		.await;
+		match res {
+			Ok(outcome) => {
+				migrated += 1;
+				let notes = match outcome {
+					JobOutcome::Done(notes) => {
+						written = true;
+						notes
+					},
+					JobOutcome::Skipped(notes) => notes
+				};
+				if notes.is_empty() {
+					summary.push(format!("- {pack}: Migrated"));
+				} else {
+					summary.push(format!("- {pack}: {}", notes.join(" ")));
+				}
+			},
+			Err(err) => {
+				error!("Failed to migrate {pack} from {url}: {err:?}");
+				summary.push(format!("- {pack}: Failed: {err:#}"));
+			}
+		}
+	}
+
+	let notes = vec![format!(
+		"Processed {migrated} of {} sticker packs from the stickerpicker index:\n{}",
+		index.packs.len(),
+		summary.join("\n")
+	)];
+	Ok(if written {
+		JobOutcome::Done(notes)
+	} else {
+		JobOutcome::Skipped(notes)
+	})
+}
+
+async fn migrate_pack(
+	room: &Room,
+	requester: &UserId,
+	args: &MigrateArgs,
+	url: &str,
+	maunium_pack: maunium::StickerPack
+) -> anyhow::Result<JobOutcome> {
+	let source = PackSource::new(SourceType::Maunium, url.to_owned(), requester);
+	let id = resolve_state_key(
+		room,
+		&slugify(&maunium_pack.id),
+		&source,
+		&maunium_pack.title,
+		args.on_collision
+	)
+	.await?;
+
+	// check if the pack was already migrated
+	let existing = read_split_stickerpack(room, &id)
 		.await
 		.context("Failed to check if sticker pack was already added to the room")?
-		.is_some()
-	{
+		.filter(|pack| pack.is_from(&source, &maunium_pack.title));
+	if existing.is_some() && args.on_existing == OnExisting::Skip {
 		info!("Skipping import of {id} sticker pack");
-		return Ok(());
+		return Ok(JobOutcome::Skipped(vec![format!(
+			"The sticker pack {id} has already been migrated, so I skipped it. Use \
+			 --force to replace it, or --merge to add new stickers to it."
+		)]));
+	}
+
+	let max_stickers = config().limits.max_stickers;
+	if maunium_pack.stickers.len() > max_stickers {
+		bail!(
+			"The sticker pack contains {} stickers, but at most {max_stickers} are \
+			 allowed",
+			maunium_pack.stickers.len()
+		);
 	}
 
 	let mut stickerpack = ponies::StickerPack {
@@ -55,17 +292,52 @@ pub(super) async fn migrate(room: &Room, pack: &str) -> anyhow::Result<()> {
 			avatar_url: None
 		}
 	};
+	let usage = config()
+		.room(room.room_id())
+		.usage
+		.unwrap_or(PackUsage::Sticker);
 	for sticker in maunium_pack.stickers {
 		stickerpack.images.insert(sticker.id, ponies::Sticker {
 			body: sticker.body,
 			info: sticker.info.image_info,
 			url: sticker.url,
-			usage: [ponies::Usage::Sticker].into_iter().collect()
+			usage: usage.usage().into_iter().collect()
 		});
 	}
 
-	write_room_state(room, "im.ponies.room_emotes", Some(&id), stickerpack)
+	let mut notes = Vec::new();
+	if args.rehost {
+		let client = room.client();
+		let db = AccountDataDatabase::load(&client)
+			.await
+			.context("Failed to load database from account data")?;
+		let rehosted = rehost_stickers(&client, &db, &mut stickerpack.images)
+			.await
+			.context("Failed to rehost the stickers")?;
+		notes.push(rehosted.note());
+		if let Err(err) = db.store(&client).await {
+			error!("Unable to store database to account data: {err:?}");
+		}
+	}
+
+	if let (Some(existing), OnExisting::Merge) = (existing, args.on_existing) {
+		let mut merged = existing.pack;
+		let added = merge_stickers(&mut merged, stickerpack.images);
+		notes.push(format!(
+			"Added {added} new stickers to the sticker pack {id}."
+		));
+		stickerpack = merged;
+	}
+	fill_avatar(&mut stickerpack);
+
+	let stickerpack = RoomStickerPack {
+		pack: stickerpack,
+		source: Some(source),
+		part: None
+	};
+	let keys = write_stickerpack(room, &id, stickerpack)
 		.await
 		.context("Failed to add the sticker pack to the room")?;
-	Ok(())
+	notes.extend(split_note(&keys));
+	Ok(JobOutcome::Done(notes))
 }
@@ -1,4 +1,8 @@
-use crate::{mxbot::state::write_queue, ADMIN, HOMESERVER, MATRIX_ID, PASSWORD};
+use crate::{
+	config::config,
+	mxbot::state::write_queue,
+	perm::{is_allowed, Command}
+};
 use futures_util::future::select;
 use indoc::indoc;
 use log::{error, info, warn};
@@ -7,43 +11,58 @@ use matrix_sdk::{
 	room::Room,
 	ruma::events::{
 		room::{
+			encrypted::OriginalSyncRoomEncryptedEvent,
 			member::StrippedRoomMemberEvent,
 			message::{
 				ForwardThread, MessageType, OriginalSyncRoomMessageEvent,
 				RoomMessageEventContent
-			}
+			},
+			tombstone::OriginalSyncRoomTombstoneEvent
 		},
 		MessageLikeEventContent
 	},
 	Client, RoomState
 };
-use ruma::{
-	events::{
-		reaction::ReactionEventContent, relation::Annotation, room::message::AddMentions
-	},
-	UserId
+use ruma::events::{
+	reaction::ReactionEventContent, relation::Annotation, room::message::AddMentions
 };
 use std::time::Duration;
 use tokio::time::sleep;
 
+mod archive;
+mod args;
+mod backup;
+mod copy;
 mod db;
+mod edit;
 mod err;
+mod export;
+mod fetch;
 mod import;
+mod media;
 mod migrate;
+mod power;
+mod rehost;
+mod session;
+mod shortcode;
+mod slug;
+mod space;
 mod state;
+mod upgrade;
 
 use anyhow::bail;
+use backup::{backup, restore};
+use copy::copy_pack;
+use edit::{add_sticker, edit_pack, set_avatar};
 use err::build_err_msg;
-use import::import;
-use migrate::migrate;
+use export::export;
+use import::{import, ImportArgs};
+use media::{is_reply, strip_reply_fallback};
+use migrate::{migrate, MigrateArgs};
+use power::{check_can_write_packs, may_manage_packs};
+use space::pack_room;
 use state::{read_queue, Job, Queue, QueuedJob};
-
-fn is_admin(sender: &UserId) -> bool {
-	ADMIN
-		.as_deref()
-		.map(|admins| admins.split([',', ' ']).any(|admin| admin == sender))
-		.unwrap_or(true)
-}
+use upgrade::carry_over_packs;
 
 async fn autojoin_handler(ev: StrippedRoomMemberEvent, room: Room, client: Client) {
 	// ignore member events for other users
@@ -58,12 +77,12 @@ async fn autojoin_handler(ev: StrippedRoomMemberEvent, room: Room, client: Clien
 
 	let room_id = room.room_id();
 
-	// ignore events that weren't sent by an admin
-	if !is_admin(&ev.sender) {
+	// ignore invitations from users that may not invite us
+	if !is_allowed(&ev.sender, Command::Invite) {
 		warn!("Rejecting invitation for {room_id}");
 		room.leave().await.ok();
 	}
-	// otherwise, the event was sent by an admin so we join the room
+	// otherwise, the event was sent by someone allowed to invite us so we join the room
 	else {
 		match room.join().await {
 			Ok(_) => info!("Successfully joined room {room_id}"),
@@ -72,6 +91,52 @@ async fn autojoin_handler(ev: StrippedRoomMemberEvent, room: Room, client: Clien
 	}
 }
 
+async fn tombstone_handler(ev: OriginalSyncRoomTombstoneEvent, room: Room) {
+	// ignore rooms that we already left
+	if room.state() != RoomState::Joined {
+		return;
+	}
+
+	let room_id = room.room_id();
+	let replacement = &ev.content.replacement_room;
+	match carry_over_packs(&room, &ev.sender, replacement).await {
+		Ok(Some((new_room, keys))) if !keys.is_empty() => {
+			info!("Copied sticker packs from {room_id} to {replacement}");
+			send(
+				&new_room,
+				RoomMessageEventContent::notice_plain(format!(
+					"This room replaces {room_id}. I copied the sticker packs {} from the \
+					 old room.",
+					keys.join(", ")
+				))
+			)
+			.await;
+		},
+		Ok(_) => {},
+		Err(err) => {
+			error!(
+				"Failed to copy sticker packs from {room_id} to {replacement}: {err:?}"
+			);
+			send(
+				&room,
+				RoomMessageEventContent::notice_plain(format!(
+					"Failed to copy the sticker packs to the new room: {err:#}"
+				))
+			)
+			.await;
+		}
+	}
+}
+
+async fn utd_handler(ev: OriginalSyncRoomEncryptedEvent, room: Room) {
+	// if we receive an encrypted event here, the sdk was unable to decrypt it
+	warn!(
+		"Unable to decrypt event {} in room {}",
+		ev.event_id,
+		room.room_id()
+	);
+}
+
 async fn send(room: &Room, content: impl MessageLikeEventContent) {
 	let room_id = room.room_id();
 	match room.send(content).await {
@@ -105,12 +170,51 @@ async fn react(room: &Room, ev: OriginalSyncRoomMessageEvent, body: &str) {
 	.await;
 }
 
+/// Respond to a command that was executed immediately.
+async fn respond(room: &Room, ev: OriginalSyncRoomMessageEvent, res: anyhow::Result<()>) {
+	match res {
+		Ok(()) => react(room, ev, "✅").await,
+		Err(err) => {
+			error!("Failed to execute command: {err:?}");
+			reply(
+				room,
+				ev,
+				RoomMessageEventContent::text_plain(format!("{err:#}"))
+			)
+			.await;
+		}
+	}
+}
+
+/// Respond to a command that was executed immediately, and reply with its notes.
+async fn respond_with_notes(
+	room: &Room,
+	ev: OriginalSyncRoomMessageEvent,
+	res: anyhow::Result<Vec<String>>
+) {
+	match res {
+		Ok(notes) if !notes.is_empty() => {
+			react(room, ev.clone(), "✅").await;
+			reply(
+				room,
+				ev,
+				RoomMessageEventContent::text_plain(notes.join("\n"))
+			)
+			.await;
+		},
+		res => respond(room, ev, res.map(|_| ())).await
+	}
+}
+
 async fn enqueue_impl(
 	room: &Room,
 	ev: OriginalSyncRoomMessageEvent,
 	job: Job
 ) -> anyhow::Result<()> {
 	let mut q = read_queue(&room.client()).await?;
+	if q.q.len() >= config().limits.max_queue_len {
+		bail!("The queue is full");
+	}
 	q.q.push_back(QueuedJob {
 		ev: ev.clone().into_full_event(room.room_id().to_owned()),
 		job
@@ -122,12 +226,66 @@ async fn enqueue_impl(
 }
 
 async fn enqueue(room: &Room, ev: OriginalSyncRoomMessageEvent, job: Job) {
-	match enqueue_impl(room, ev, job).await {
+	match enqueue_impl(room, ev.clone(), job).await {
 		Ok(_) => info!("Sucessfully enqueued job"),
-		Err(err) => error!("Error enqueueing job: {err}")
+		Err(err) => {
+			error!("Error enqueueing job: {err}");
+			reply(
+				room,
+				ev,
+				RoomMessageEventContent::text_plain(format!("{err:#}"))
+			)
+			.await;
+		}
 	}
 }
 
+fn command_of(body: &str) -> Option<Command> {
+	let cmd = body.split_once(' ').map_or(body, |(cmd, _)| cmd);
+	Some(match cmd {
+		"!help" => Command::Help,
+		"!import" => Command::Import,
+		"!migrate" => Command::Migrate,
+		"!avatar" => Command::Avatar,
+		"!add" => Command::Add,
+		"!pack" => Command::Pack,
+		"!copy" => Command::Copy,
+		"!move" => Command::Move,
+		"!export" => Command::Export,
+		"!clear" => Command::ClearQueue,
+		"!backup" => Command::Backup,
+		"!restore" => Command::Restore,
+		_ => return None
+	})
+}
+
+/// Return the job for the command if the body is a command that gets queued.
+fn queued_job(body: &str) -> Option<Job> {
+	if let Some(args) = command_args(body, "!import") {
+		Some(Job::Import(args.to_owned()))
+	} else {
+		command_args(body, "!migrate").map(|args| Job::Migrate(args.to_owned()))
+	}
+}
+
+/// Whether the job writes its sticker packs to the parent space of the room.
+fn job_uses_space(job: &Job, is_reply: bool) -> bool {
+	match job {
+		Job::Import(args) => {
+			ImportArgs::parse(args, is_reply).is_ok_and(|args| args.space)
+		},
+		Job::Migrate(args) => {
+			MigrateArgs::parse(args, is_reply).is_ok_and(|args| args.space)
+		},
+	}
+}
+
+/// Return the arguments of the command if the body is that command.
+fn command_args<'a>(body: &'a str, cmd: &str) -> Option<&'a str> {
+	let args = body.strip_prefix(cmd)?;
+	(args.is_empty() || args.starts_with(' ')).then(|| args.trim_start())
+}
+
 async fn message_handler(ev: OriginalSyncRoomMessageEvent, room: Room, client: Client) {
 	// don't reply to our own messages
 	if ev.sender == client.user_id().unwrap() {
@@ -138,11 +296,78 @@ async fn message_handler(ev: OriginalSyncRoomMessageEvent, room: Room, client: C
 		return;
 	};
 
-	let body = text_content.body.trim_end();
+	let body = strip_reply_fallback(&text_content.body).trim_end();
 	if !body.starts_with('!') {
 		return;
 	}
 
+	// ignore users that don't have any role
+	if config().permissions.role_of(&ev.sender).is_none() {
+		return;
+	}
+
+	// check the permissions for the command
+	if let Some(cmd) = command_of(body) {
+		if !is_allowed(&ev.sender, cmd) {
+			reply(
+				&room,
+				ev,
+				RoomMessageEventContent::text_plain(
+					"You are not allowed to use this command."
+				)
+			)
+			.await;
+			return;
+		}
+
+		// jobs that write to the space need to be checked against the space instead
+		let space = queued_job(body)
+			.is_some_and(|job| job_uses_space(&job, is_reply(&ev.content)));
+		if space {
+			if let Err(err) = pack_room(&room, &ev.sender, true).await {
+				reply(
+					&room,
+					ev,
+					RoomMessageEventContent::text_plain(format!("{err:#}"))
+				)
+				.await;
+				return;
+			}
+		} else if cmd.changes_packs() {
+			match may_manage_packs(&room, &ev.sender).await {
+				Ok(true) => {},
+				Ok(false) => {
+					reply(
+						&room,
+						ev,
+						RoomMessageEventContent::text_plain(
+							"Your power level in this room is too low to manage its \
+							 sticker packs."
+						)
+					)
+					.await;
+					return;
+				},
+				Err(err) => {
+					error!("Failed to check power levels: {err:?}");
+					react(&room, ev, "🟥").await;
+					return;
+				}
+			}
+
+			// make sure we can actually write the result before queueing the job
+			if let Err(err) = check_can_write_packs(&room).await {
+				reply(
+					&room,
+					ev,
+					RoomMessageEventContent::text_plain(err.to_string())
+				)
+				.await;
+				return;
+			}
+		}
+	}
+
 	// help message
 	if body == "!help" {
 		reply(
@@ -158,9 +383,69 @@ async fn message_handler(ev: OriginalSyncRoomMessageEvent, room: Room, client: C
 
 						!help  --  Show this help message
 
-						!import <pack>  --  Import a telegram sticker pack.
+						!import <pack> [--overwrite | --rename] [--space] [--shortcodes
+						index|emoji|name]  --  Import a telegram sticker pack. The
+						shortcodes are the names of the stickers' emoji by default, use
+						--shortcodes to number the stickers or use the emoji themselves
+						instead. Custom emoji sets (t.me/addemoji links) are imported as
+						emoticons. To import a zip archive of images instead, reply to it
+						with !import. The archive can contain a manifest.json with the
+						title of the pack and the shortcode and body of each image.
+
+						!migrate <pack> [--force | --merge] [--overwrite | --rename]
+						[--rehost] [--space]  --  Migrate a maunium sticker pack. The url
+						can also point to the index.json of a stickerpicker deployment,
+						or its base url, to migrate all of its packs. Packs stored on
+						matrix can be migrated using their mxc:// url, or by replying to
+						the json file with !migrate. If a pack was migrated before, use
+						--force to replace it or --merge to add new stickers to it. Use
+						--rehost to copy stickers from other homeservers to mine.
+
+						!avatar <pack> <sticker>  --  Use a sticker as the avatar of a
+						sticker pack.
+
+						!add <pack> <shortcode> [--usage sticker|emoticon|both]  --
+						Reply to an image or sticker with this command to add it to a
+						sticker pack. The pack is created if it doesn't exist yet.
+
+						!pack rename <pack> <old> <new>  --  Change the shortcode of a
+						sticker.
+
+						!pack body <pack> <shortcode> <text>  --  Change the body of a
+						sticker.
+
+						!pack rm <pack> <shortcode>  --  Remove a sticker.
+
+						!pack move <pack> <shortcode> <position>  --  Move a sticker to a
+						different position, starting at 1.
+
+						!pack usage <pack> <shortcode> sticker|emoticon|both  --  Change
+						where a sticker can be used.
+
+						!copy <pack> <room>|--space [--as <id>]  --  Copy a sticker pack to a
+						different room, optionally using a different id there. You need
+						to be allowed to manage the sticker packs of both rooms.
+
+						!move <pack> <room>|--space [--as <id>]  --  Like !copy, but remove the
+						sticker pack from this room afterwards.
+
+						!export <pack> [--format maunium|ponies]  --  Upload a sticker
+						pack as a json file, by default in the format used by maunium's
+						stickerpicker.
+
+						!backup  --  Upload a backup of the sticker packs of all rooms I
+						am in (admin only).
+
+						!restore [<room> [<target room>]]  --  Reply to a backup with
+						this command to restore its sticker packs, optionally only those
+						of one room or into a different room (admin only).
+
+						If a different sticker pack already uses the same id, use
+						--overwrite to replace it or --rename to add the pack under a
+						different id.
 
-						!migrate <pack>  --  Migrate a maunium sticker pack.
+						Use --space to add the sticker pack to the parent space of this
+						room instead, so that all rooms of the space can use it.
 					"#},
 				indoc! {r#"
 						<p>This is tg2mx_bot, a bot that can import sticker packs from
@@ -171,26 +456,147 @@ async fn message_handler(ev: OriginalSyncRoomMessageEvent, room: Room, client: C
 
 						<ul>
 						  <li><code>!help</code>  --  Show this help message</li>
-						  <li><code>!import</code> &lt;pack&gt;  --  Import a telegram
-						      sticker pack.</li>
-						  <li><code>!migrate</code> &lt;pack&gt;  --  Migrate a maunium
-						      sticker pack.</li>
+						  <li><code>!import</code> &lt;pack&gt; [--overwrite | --rename]
+						      [--space] [--shortcodes index|emoji|name]  --  Import a telegram
+						      sticker pack. The shortcodes are the names of the stickers'
+						      emoji by default, use <code>--shortcodes</code> to number the
+						      stickers or use the emoji themselves instead. Custom emoji sets
+						      (<code>t.me/addemoji</code> links) are imported as emoticons.
+						      To import a zip archive of images instead, reply to it with
+						      <code>!import</code>. The archive can contain a
+						      <code>manifest.json</code> with the title of the pack and the
+						      shortcode and body of each image.</li>
+						  <li><code>!migrate</code> &lt;pack&gt; [--force | --merge]
+						      [--overwrite | --rename] [--rehost] [--space]  --  Migrate a
+						      maunium sticker pack. The url can also point to the
+						      <code>index.json</code> of a stickerpicker deployment, or its
+						      base url, to migrate all of its packs. Packs stored on matrix
+						      can be migrated using their <code>mxc://</code> url, or by
+						      replying to the json file with <code>!migrate</code>. If a
+						      pack was migrated before, use <code>--force</code> to replace
+						      it or <code>--merge</code> to add new stickers to it. Use
+						      <code>--rehost</code> to copy stickers from other homeservers
+						      to mine.</li>
+						  <li><code>!avatar</code> &lt;pack&gt; &lt;sticker&gt;  --  Use a
+						      sticker as the avatar of a sticker pack.</li>
+						  <li><code>!add</code> &lt;pack&gt; &lt;shortcode&gt; [--usage
+						      sticker|emoticon|both]  --  Reply to an image or sticker with
+						      this command to add it to a sticker pack. The pack is created
+						      if it doesn't exist yet.</li>
+						  <li><code>!pack rename</code> &lt;pack&gt; &lt;old&gt;
+						      &lt;new&gt;  --  Change the shortcode of a sticker.</li>
+						  <li><code>!pack body</code> &lt;pack&gt; &lt;shortcode&gt;
+						      &lt;text&gt;  --  Change the body of a sticker.</li>
+						  <li><code>!pack rm</code> &lt;pack&gt; &lt;shortcode&gt;  --
+						      Remove a sticker.</li>
+						  <li><code>!pack move</code> &lt;pack&gt; &lt;shortcode&gt;
+						      &lt;position&gt;  --  Move a sticker to a different position,
+						      starting at 1.</li>
+						  <li><code>!pack usage</code> &lt;pack&gt; &lt;shortcode&gt;
+						      sticker|emoticon|both  --  Change where a sticker can be
+						      used.</li>
+						  <li><code>!copy</code> &lt;pack&gt; &lt;room&gt;|--space [--as
+						      &lt;id&gt;]
+						      --  Copy a sticker pack to a different room, optionally using
+						      a different id there. You need to be allowed to manage the
+						      sticker packs of both rooms.</li>
+						  <li><code>!move</code> &lt;pack&gt; &lt;room&gt;|--space [--as
+						      &lt;id&gt;]
+						      --  Like <code>!copy</code>, but remove the sticker pack from
+						      this room afterwards.</li>
+						  <li><code>!export</code> &lt;pack&gt; [--format maunium|ponies]
+						      --  Upload a sticker pack as a json file, by default in the
+						      format used by maunium's stickerpicker.</li>
+						  <li><code>!backup</code>  --  Upload a backup of the sticker
+						      packs of all rooms I am in (admin only).</li>
+						  <li><code>!restore</code> [&lt;room&gt; [&lt;target room&gt;]]
+						      --  Reply to a backup with this command to restore its sticker
+						      packs, optionally only those of one room or into a different
+						      room (admin only).</li>
 						</ul>
+
+						<p>If a different sticker pack already uses the same id, use
+						<code>--overwrite</code> to replace it or <code>--rename</code> to
+						add the pack under a different id.</p>
+
+						<p>Use <code>--space</code> to add the sticker pack to the parent
+						space of this room instead, so that all rooms of the space can use
+						it.</p>
 					"#}
 			)
 		)
 		.await;
 	}
 	// import tg sticker pack
-	else if let Some(pack) = body.strip_prefix("!import ") {
-		enqueue(&room, ev, Job::Import(pack.to_owned())).await;
+	else if let Some(args) = command_args(body, "!import") {
+		match ImportArgs::parse(args, is_reply(&ev.content)) {
+			Ok(_) => enqueue(&room, ev, Job::Import(args.to_owned())).await,
+			Err(err) => {
+				reply(
+					&room,
+					ev,
+					RoomMessageEventContent::text_plain(err.to_string())
+				)
+				.await
+			},
+		}
 	}
 	// import maunium sticker pack
-	else if let Some(pack) = body.strip_prefix("!migrate ") {
-		enqueue(&room, ev, Job::Migrate(pack.to_owned())).await;
+	else if let Some(args) = command_args(body, "!migrate") {
+		match MigrateArgs::parse(args, is_reply(&ev.content)) {
+			Ok(_) => enqueue(&room, ev, Job::Migrate(args.to_owned())).await,
+			Err(err) => {
+				reply(
+					&room,
+					ev,
+					RoomMessageEventContent::text_plain(err.to_string())
+				)
+				.await
+			},
+		}
+	}
+	// change the avatar of a sticker pack
+	else if let Some(args) = command_args(body, "!avatar") {
+		let res = set_avatar(&room, args).await;
+		respond(&room, ev, res).await;
+	}
+	// add an image or sticker to a sticker pack
+	else if let Some(args) = command_args(body, "!add") {
+		let res = add_sticker(&room, &ev.sender, &ev.content, args).await;
+		respond(&room, ev, res).await;
+	}
+	// edit a sticker of a sticker pack
+	else if let Some(args) = command_args(body, "!pack") {
+		let res = edit_pack(&room, args).await;
+		respond(&room, ev, res).await;
+	}
+	// copy a sticker pack to a different room
+	else if let Some(args) = command_args(body, "!copy") {
+		let res = copy_pack(&room, &ev.sender, args, false).await;
+		respond_with_notes(&room, ev, res).await;
+	}
+	// move a sticker pack to a different room
+	else if let Some(args) = command_args(body, "!move") {
+		let res = copy_pack(&room, &ev.sender, args, true).await;
+		respond_with_notes(&room, ev, res).await;
+	}
+	// export a sticker pack
+	else if let Some(args) = command_args(body, "!export") {
+		let res = export(&room, args).await;
+		respond(&room, ev, res).await;
+	}
+	// back up all sticker packs
+	else if body == "!backup" {
+		let res = backup(&room).await;
+		respond_with_notes(&room, ev, res).await;
+	}
+	// restore sticker packs from a backup
+	else if let Some(args) = command_args(body, "!restore") {
+		let res = restore(&room, &ev.content, args).await;
+		respond_with_notes(&room, ev, res).await;
 	}
 	// clear the queue
-	else if body == "!clear queue" && is_admin(&ev.sender) {
+	else if body == "!clear queue" {
 		let emoji = match write_queue(&client, &Queue::default()).await {
 			Ok(_) => "✅",
 			Err(err) => {
@@ -213,19 +619,60 @@ async fn message_handler(ev: OriginalSyncRoomMessageEvent, room: Room, client: C
 	}
 }
 
+/// The result of a queued job that didn't fail.
+pub(super) enum JobOutcome {
+	/// At least one sticker pack was written.
+	Done(Vec<String>),
+	/// Nothing was written, e.g. because the sticker pack had been migrated already.
+	Skipped(Vec<String>)
+}
+
+/// Tell the user that a sticker pack had to be split, if it was.
+fn split_note(keys: &[String]) -> Option<String> {
+	(keys.len() > 1).then(|| {
+		format!(
+			"The sticker pack was too large for a single state event and has been split \
+			 into {} parts: {}",
+			keys.len(),
+			keys.join(", ")
+		)
+	})
+}
+
 async fn run_queued_job(client: &Client, job: &QueuedJob) -> anyhow::Result<()> {
 	let Some(room) = client.get_room(&job.ev.room_id) else {
 		bail!("Failed to find room for job {job:?}")
 	};
 
-	let res = match &job.job {
-		Job::Import(pack) => import(&room, pack).await,
-		Job::Migrate(pack) => migrate(&room, pack).await
-	};
+	let res = async {
+		// the power levels might have changed while the job was queued
+		let space = job_uses_space(&job.job, is_reply(&job.ev.content));
+		let target = pack_room(&room, &job.ev.sender, space).await?;
+		check_can_write_packs(&target).await?;
+		match &job.job {
+			Job::Import(args) => import(&room, &job.ev, args).await.map(JobOutcome::Done),
+			Job::Migrate(args) => migrate(&room, &job.ev, args).await
+		}
+	}
+	.await;
 
 	let ev = job.ev.clone().into();
 	match &res {
-		Ok(()) => react(&room, ev, "✅").await,
+		Ok(outcome) => {
+			let (emoji, notes) = match outcome {
+				JobOutcome::Done(notes) => ("✅", notes),
+				JobOutcome::Skipped(notes) => ("ℹ️", notes)
+			};
+			react(&room, ev.clone(), emoji).await;
+			if !notes.is_empty() {
+				reply(
+					&room,
+					ev,
+					RoomMessageEventContent::text_plain(notes.join("\n"))
+				)
+				.await;
+			}
+		},
 		Err(err) => {
 			error!("Failed to execute job {job:?}: {err:?}");
 			react(&room, ev.clone(), "🟥").await;
@@ -257,18 +704,27 @@ async fn run_queued_job(client: &Client, job: &QueuedJob) -> anyhow::Result<()>
 
 pub(super) async fn run() -> anyhow::Result<()> {
 	let client = Client::builder()
-		.homeserver_url(HOMESERVER.as_deref().unwrap())
+		.homeserver_url(config().homeserver())
+		.sqlite_store(
+			&config().storage.store_path,
+			config().storage.store_passphrase.as_deref()
+		)
 		.build()
 		.await?;
-	client
-		.matrix_auth()
-		.login_username(MATRIX_ID.as_deref().unwrap(), PASSWORD.as_deref().unwrap())
-		.initial_device_display_name("tg2mx bot")
-		.send()
-		.await?;
+	session::login(&client).await?;
+	info!(
+		"Using device {} with ed25519 key {}",
+		client.device_id().unwrap(),
+		client
+			.encryption()
+			.ed25519_key()
+			.await
+			.as_deref()
+			.unwrap_or("<unknown>")
+	);
 	client
 		.account()
-		.set_display_name(Some("TG2MX Sticker Import BOT"))
+		.set_display_name(Some(&config().matrix.display_name))
 		.await
 		.ok();
 	info!("Logged in successfully");
@@ -278,7 +734,9 @@ pub(super) async fn run() -> anyhow::Result<()> {
 
 	// from now on, start handling events
 	client.add_event_handler(autojoin_handler);
+	client.add_event_handler(tombstone_handler);
 	client.add_event_handler(message_handler);
+	client.add_event_handler(utd_handler);
 
 	// keep syncing forever
 	let sync_fut = async {
@@ -0,0 +1,64 @@
+use crate::config::config;
+use anyhow::{anyhow, bail};
+use matrix_sdk::{
+	room::Room,
+	ruma::{
+		events::{
+			room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+			TimelineEventType
+		},
+		UserId
+	}
+};
+
+async fn power_levels(room: &Room) -> anyhow::Result<RoomPowerLevels> {
+	Ok(room
+		.get_state_event_static::<RoomPowerLevelsEventContent>()
+		.await?
+		.ok_or_else(|| anyhow!("Room {} has no power levels", room.room_id()))?
+		.deserialize()?
+		.power_levels())
+}
+
+/// The power level needed to send the event in the room.
+fn required_level(power_levels: &RoomPowerLevels, event_type: &str) -> i64 {
+	power_levels
+		.events
+		.get(&TimelineEventType::from(event_type))
+		.copied()
+		.unwrap_or(power_levels.state_default)
+		.into()
+}
+
+/// Check whether the user may manage the sticker packs of the room. This requires
+/// either the configured threshold for the room, or the power level needed to send
+/// `im.ponies.room_emotes` state events.
+pub(super) async fn may_manage_packs(room: &Room, user: &UserId) -> anyhow::Result<bool> {
+	let power_levels = power_levels(room).await?;
+	let required = config()
+		.room(room.room_id())
+		.pack_power_level
+		.or(config().permissions.pack_power_level)
+		.unwrap_or_else(|| required_level(&power_levels, "im.ponies.room_emotes"));
+	Ok(i64::from(power_levels.for_user(user)) >= required)
+}
+
+/// Check that the bot itself is allowed to write sticker packs to the room, returning
+/// an error that tells the user how to fix it otherwise.
+pub(super) async fn check_can_write_packs(room: &Room) -> anyhow::Result<()> {
+	let client = room.client();
+	let user_id = client
+		.user_id()
+		.ok_or_else(|| anyhow!("How can we not have a user id?"))?;
+	let power_levels = power_levels(room).await?;
+	let required = required_level(&power_levels, "im.ponies.room_emotes");
+	let own = i64::from(power_levels.for_user(user_id));
+	if own < required {
+		bail!(
+			"I need power level {required} to add sticker packs to this room, but I \
+			 only have power level {own}. Please give me power level {required} or \
+			 lower the power level required for im.ponies.room_emotes events to {own}."
+		);
+	}
+	Ok(())
+}
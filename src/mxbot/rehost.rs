@@ -0,0 +1,103 @@
+use super::{db::AccountDataDatabase, media::download_media};
+use crate::config::config;
+use anyhow::anyhow;
+use indexmap::IndexMap;
+use log::warn;
+use matrix_sdk::{
+	ruma::{events::room::MediaSource, OwnedMxcUri},
+	Client
+};
+use mstickerlib::matrix::sticker_formats::ponies;
+
+/// The result of rehosting the stickers of a pack.
+#[derive(Default)]
+pub(super) struct Rehosted {
+	pub(super) rehosted: usize,
+	pub(super) failed: usize
+}
+
+impl Rehosted {
+	pub(super) fn note(&self) -> String {
+		let mut note = format!("Rehosted {} stickers.", self.rehosted);
+		if self.failed > 0 {
+			note += &format!(
+				" {} stickers could not be rehosted and still point to their original \
+				 server.",
+				self.failed
+			);
+		}
+		note
+	}
+}
+
+/// Download the media and upload it to our own homeserver, returning the new url.
+async fn rehost_media(
+	client: &Client,
+	db: &AccountDataDatabase,
+	url: &str,
+	mimetype: &str
+) -> anyhow::Result<String> {
+	let source = MediaSource::Plain(OwnedMxcUri::from(url));
+	let data = download_media(client, &source, config().limits.max_file_size).await?;
+	db.upload(client, data, &mimetype.parse()?).await
+}
+
+/// Rehost all stickers that are not stored on our own homeserver, rewriting their urls.
+/// Thumbnails on foreign servers are removed from the sticker info, as clients can
+/// fall back to the sticker itself.
+pub(super) async fn rehost_stickers(
+	client: &Client,
+	db: &AccountDataDatabase,
+	stickers: &mut IndexMap<String, ponies::Sticker>
+) -> anyhow::Result<Rehosted> {
+	let own_server = client
+		.user_id()
+		.ok_or_else(|| anyhow!("How can we not have a user id?"))?
+		.server_name()
+		.to_owned();
+	let is_foreign = |url: &str| match OwnedMxcUri::from(url).server_name() {
+		Ok(server) => server.as_str() != own_server.as_str(),
+		Err(_) => true
+	};
+
+	let mut rehosted = Rehosted::default();
+	for (shortcode, sticker) in stickers.iter_mut() {
+		if !is_foreign(&sticker.url) {
+			continue;
+		}
+
+		let mut info = serde_json::to_value(&sticker.info)?;
+		let mimetype = info
+			.get("mimetype")
+			.and_then(|mimetype| mimetype.as_str())
+			.unwrap_or("application/octet-stream")
+			.to_owned();
+		match rehost_media(client, db, &sticker.url, &mimetype).await {
+			Ok(url) => {
+				sticker.url = url;
+				rehosted.rehosted += 1;
+			},
+			Err(err) => {
+				warn!(
+					"Failed to rehost sticker {shortcode} ({}): {err:?}",
+					sticker.url
+				);
+				rehosted.failed += 1;
+				continue;
+			}
+		}
+
+		let foreign_thumbnail = info
+			.get("thumbnail_url")
+			.and_then(|url| url.as_str())
+			.is_some_and(&is_foreign);
+		if foreign_thumbnail {
+			if let Some(info) = info.as_object_mut() {
+				info.remove("thumbnail_url");
+				info.remove("thumbnail_info");
+			}
+			sticker.info = serde_json::from_value(info)?;
+		}
+	}
+	Ok(rehosted)
+}
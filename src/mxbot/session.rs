@@ -0,0 +1,119 @@
+use crate::config::config;
+use anyhow::{anyhow, bail, Context as _};
+use log::info;
+use matrix_sdk::{
+	matrix_auth::{MatrixSession, MatrixSessionTokens},
+	ruma::{OwnedDeviceId, OwnedUserId},
+	Client, SessionMeta
+};
+use mstickerlib::get_client;
+use serde::Deserialize;
+use std::{
+	fs::{self, OpenOptions},
+	io::{ErrorKind, Write as _},
+	path::Path
+};
+
+fn load_session(path: &Path) -> anyhow::Result<Option<MatrixSession>> {
+	let bytes = match fs::read(path) {
+		Ok(bytes) => bytes,
+		Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(err) => return Err(err.into())
+	};
+	Ok(Some(serde_json::from_slice(&bytes)?))
+}
+
+fn store_session(path: &Path, session: &MatrixSession) -> anyhow::Result<()> {
+	let mut opts = OpenOptions::new();
+	opts.write(true).create(true).truncate(true);
+	// the session file contains our access token, so keep it private
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+	let mut file = opts.open(path)?;
+	file.write_all(&serde_json::to_vec_pretty(session)?)?;
+	Ok(())
+}
+
+#[derive(Deserialize)]
+struct WhoAmI {
+	user_id: OwnedUserId,
+	device_id: Option<OwnedDeviceId>
+}
+
+/// Build a session from an access token by asking the homeserver who we are.
+async fn session_from_token(
+	client: &Client,
+	access_token: &str
+) -> anyhow::Result<MatrixSession> {
+	let whoami: WhoAmI = get_client()
+		.get(format!(
+			"{}_matrix/client/v3/account/whoami",
+			client.homeserver()
+		))
+		.header("Authorization", format!("Bearer {access_token}"))
+		.send()
+		.await?
+		.error_for_status()?
+		.json()
+		.await?;
+	let Some(device_id) = whoami.device_id else {
+		bail!("The access token is not associated with a device");
+	};
+	Ok(MatrixSession {
+		meta: SessionMeta {
+			user_id: whoami.user_id,
+			device_id
+		},
+		tokens: MatrixSessionTokens {
+			access_token: access_token.to_owned(),
+			refresh_token: None
+		}
+	})
+}
+
+/// Log in to the homeserver. This restores the session from the session file if it
+/// exists, otherwise uses the access token if one was given, and only falls back to
+/// a password login if neither is available. New sessions are written to the session
+/// file so that we don't create a new device on every start.
+pub(super) async fn login(client: &Client) -> anyhow::Result<()> {
+	let path = &config().storage.session_file;
+	let auth = client.matrix_auth();
+
+	if let Some(session) = load_session(path)
+		.with_context(|| format!("Failed to read session file {}", path.display()))?
+	{
+		info!("Restoring session from {}", path.display());
+		auth.restore_session(session)
+			.await
+			.context("Failed to restore session")?;
+		return Ok(());
+	}
+
+	let session = if let Some(access_token) = config().matrix.access_token.as_deref() {
+		info!("Logging in using the access token");
+		let session = session_from_token(client, access_token)
+			.await
+			.context("Failed to look up the access token")?;
+		auth.restore_session(session.clone())
+			.await
+			.context("Failed to restore session")?;
+		session
+	} else {
+		info!("Logging in using the password");
+		let (Some(user), Some(password)) =
+			(&config().matrix.user, &config().matrix.password)
+		else {
+			bail!("Missing user id or password for the first login");
+		};
+		auth.login_username(user, password)
+			.initial_device_display_name(&config().matrix.device_name)
+			.send()
+			.await?;
+		auth.session()
+			.ok_or_else(|| anyhow!("How can we not have a session after logging in?"))?
+	};
+
+	store_session(path, &session)
+		.with_context(|| format!("Failed to write session file {}", path.display()))?;
+	Ok(())
+}
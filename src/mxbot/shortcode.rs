@@ -0,0 +1,69 @@
+use super::state::unique_shortcode;
+use anyhow::bail;
+use heck::ToSnakeCase;
+use indexmap::IndexMap;
+use mstickerlib::matrix::sticker_formats::ponies;
+use std::str::FromStr;
+
+/// How the shortcodes of imported stickers are generated.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(super) enum ShortcodeStyle {
+	/// The position of the sticker in the pack, e.g. `sticker1`.
+	Index,
+	/// The emoji of the sticker itself.
+	Emoji,
+	/// The CLDR name of the sticker's emoji, e.g. `grinning_face`.
+	#[default]
+	Name
+}
+
+impl FromStr for ShortcodeStyle {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> anyhow::Result<Self> {
+		Ok(match s {
+			"index" => Self::Index,
+			"emoji" => Self::Emoji,
+			"name" => Self::Name,
+			_ => bail!("Unknown shortcode style {s}, expected index, emoji or name")
+		})
+	}
+}
+
+/// The CLDR name of the emoji in snake case.
+fn emoji_name(emoji: &str) -> Option<String> {
+	// telegram doesn't always include the variation selector
+	let emoji = emojis::get(emoji)
+		.or_else(|| emojis::get(emoji.trim_end_matches('\u{fe0f}')))
+		.or_else(|| emojis::get(&format!("{emoji}\u{fe0f}")))?;
+	Some(emoji.name().to_snake_case()).filter(|name| !name.is_empty())
+}
+
+impl ShortcodeStyle {
+	/// The shortcode for the `i`th sticker (starting at 0), falling back to its index
+	/// if it has no known emoji.
+	fn shortcode(self, i: usize, emoji: Option<&str>) -> String {
+		let shortcode = match (self, emoji) {
+			(Self::Emoji, Some(emoji)) => Some(emoji.to_owned()),
+			(Self::Name, Some(emoji)) => emoji_name(emoji),
+			_ => None
+		};
+		shortcode.unwrap_or_else(|| format!("sticker{}", i + 1))
+	}
+
+	/// Replace the shortcodes of the stickers. `emojis` contains the emoji of the
+	/// stickers, in the same order. Duplicate shortcodes get a numeric suffix.
+	pub(super) fn apply(
+		self,
+		images: IndexMap<String, ponies::Sticker>,
+		emojis: &[Option<String>]
+	) -> IndexMap<String, ponies::Sticker> {
+		let mut renamed = IndexMap::new();
+		for (i, ((_, sticker), emoji)) in images.into_iter().zip(emojis).enumerate() {
+			let shortcode = self.shortcode(i, emoji.as_deref());
+			let shortcode = unique_shortcode(&renamed, &shortcode);
+			renamed.insert(shortcode, sticker);
+		}
+		renamed
+	}
+}
@@ -0,0 +1,80 @@
+use super::{
+	args::Args,
+	state::{read_stickerpack, PackSource}
+};
+use anyhow::{bail, Context as _};
+use heck::ToSnakeCase;
+use matrix_sdk::room::Room;
+
+/// Turn a name into a state key for a sticker pack. The result is never empty.
+pub(super) fn slugify(name: &str) -> String {
+	let mut slug = name.to_snake_case();
+	slug.retain(|ch| ch.is_alphanumeric());
+	if slug.is_empty() {
+		// FNV-1a, so that the same name always results in the same state key
+		let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+			(hash ^ byte as u64).wrapping_mul(0x100000001b3)
+		});
+		slug = format!("pack{hash:016x}");
+	}
+	slug
+}
+
+/// What to do if a different sticker pack already uses a state key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum OnCollision {
+	Fail,
+	Overwrite,
+	Rename
+}
+
+impl OnCollision {
+	pub(super) const SWITCHES: [&'static str; 2] = ["--overwrite", "--rename"];
+
+	pub(super) fn from_args(args: &Args) -> anyhow::Result<Self> {
+		Ok(
+			match (args.switch("--overwrite"), args.switch("--rename")) {
+				(false, false) => Self::Fail,
+				(true, false) => Self::Overwrite,
+				(false, true) => Self::Rename,
+				(true, true) => bail!("--overwrite and --rename cannot be used together")
+			}
+		)
+	}
+}
+
+/// Find the state key for a sticker pack. Re-importing a pack from the same source
+/// reuses its state key, while a different pack with the same slug is treated as a
+/// collision. Packs without source metadata are identified by their display name.
+pub(super) async fn resolve_state_key(
+	room: &Room,
+	slug: &str,
+	source: &PackSource,
+	display_name: &str,
+	on_collision: OnCollision
+) -> anyhow::Result<String> {
+	for i in 1 .. {
+		let key = match i {
+			1 => slug.to_owned(),
+			i => format!("{slug}{i}")
+		};
+		let existing = read_stickerpack(room, &key).await.context(
+			"Failed to check if the sticker pack was already added to the room"
+		)?;
+		match existing {
+			None => return Ok(key),
+			Some(pack) if pack.is_from(source, display_name) => return Ok(key),
+			Some(pack) => match on_collision {
+				OnCollision::Fail => bail!(
+					"A different sticker pack ({}) already uses the id {key}. Use \
+					 --overwrite to replace it, or --rename to add this pack under a \
+					 different id.",
+					pack.pack.pack.display_name
+				),
+				OnCollision::Overwrite => return Ok(key),
+				OnCollision::Rename => continue
+			}
+		}
+	}
+	unreachable!()
+}
@@ -0,0 +1,67 @@
+use super::power::{check_can_write_packs, may_manage_packs};
+use anyhow::{anyhow, bail};
+use matrix_sdk::{
+	deserialized_responses::SyncOrStrippedState,
+	room::Room,
+	ruma::{
+		events::{space::parent::SpaceParentEventContent, SyncStateEvent},
+		UserId
+	},
+	RoomState
+};
+
+/// Find the parent space of the room, using its `m.space.parent` events. If the room
+/// has several parents, one of them has to be marked as canonical.
+pub(super) async fn parent_space(room: &Room) -> anyhow::Result<Room> {
+	let mut parents = Vec::new();
+	for ev in room
+		.get_state_events_static::<SpaceParentEventContent>()
+		.await?
+	{
+		// events without any via servers are not valid
+		if let SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) =
+			ev.deserialize()?
+		{
+			if !ev.content.via.is_empty() {
+				parents.push((ev.state_key, ev.content.canonical));
+			}
+		}
+	}
+
+	let space_id = match parents.as_slice() {
+		[] => bail!("This room is not part of a space"),
+		[(space_id, _)] => space_id,
+		parents => parents
+			.iter()
+			.find(|(_, canonical)| *canonical)
+			.map(|(space_id, _)| space_id)
+			.ok_or_else(|| {
+				anyhow!("This room is part of several spaces, none of them canonical")
+			})?
+	};
+	room.client()
+		.get_room(space_id)
+		.filter(|space| space.state() == RoomState::Joined)
+		.ok_or_else(|| {
+			anyhow!("I am not in the space {space_id}, please invite me first")
+		})
+}
+
+/// Return the room that sticker packs should be written to, which is the parent space
+/// of the room if `space` is set. The requester must be allowed to manage the sticker
+/// packs of the space.
+pub(super) async fn pack_room(
+	room: &Room,
+	requester: &UserId,
+	space: bool
+) -> anyhow::Result<Room> {
+	if !space {
+		return Ok(room.clone());
+	}
+	let space = parent_space(room).await?;
+	if !may_manage_packs(&space, requester).await? {
+		bail!("Your power level in the space is too low to manage its sticker packs.");
+	}
+	check_can_write_packs(&space).await?;
+	Ok(space)
+}
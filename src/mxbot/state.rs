@@ -1,24 +1,38 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use indexmap::IndexMap;
-use log::{error, info};
+use log::{error, info, warn};
 use matrix_sdk::{
 	deserialized_responses::RawAnySyncOrStrippedState,
 	room::Room,
 	ruma::{
+		api::client::{error::ErrorKind, state::get_state_events_for_key},
 		events::{
 			room::message::OriginalRoomMessageEvent, MessageLikeEventContent,
 			OriginalMessageLikeEvent
 		},
 		serde::Raw,
-		MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId
+		MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, UserId
 	},
 	Client
 };
 use monostate::MustBe;
 use mstickerlib::{database, get_client, matrix::sticker_formats::ponies};
 use reqwest::StatusCode;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{borrow::Borrow, collections::VecDeque};
+use serde::{
+	de::{self, DeserializeOwned, Error as _},
+	Deserialize, Deserializer, Serialize, Serializer
+};
+use serde_json::json;
+use std::{
+	borrow::Borrow,
+	collections::{BTreeMap, VecDeque},
+	future::Future
+};
+use tokio::sync::Mutex;
+
+/// Serialises the read-modify-write cycles of commands that change sticker packs, so
+/// that concurrent commands don't overwrite each other's changes.
+pub(super) static PACK_LOCK: Mutex<()> = Mutex::const_new(());
 
 pub(super) async fn read_account_data<T>(
 	client: &Client,
@@ -71,6 +85,13 @@ where
 	Ok(())
 }
 
+/// The part of a state event we care about.
+#[derive(Deserialize)]
+struct StateEvent {
+	#[serde(default)]
+	content: serde_json::Value
+}
+
 pub(super) async fn read_room_state<T>(
 	room: &Room,
 	key: &str,
@@ -84,10 +105,18 @@ where
 		.await?;
 	Ok(ev
 		.map(|ev| match ev {
-			RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as(),
+			RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<StateEvent>(),
 			RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as()
 		})
-		.transpose())
+		.transpose()
+		.and_then(|ev| match ev {
+			// an empty content means the state event was removed
+			Some(StateEvent {
+				content: serde_json::Value::Object(content)
+			}) if content.is_empty() => Ok(None),
+			Some(ev) => serde_json::from_value(ev.content).map(Some),
+			None => Ok(None)
+		}))
 }
 
 pub(super) async fn write_room_state<T>(
@@ -175,11 +204,41 @@ pub(super) struct MediaMap {
 	pub(super) map: IndexMap<MediaHash, MediaCache>
 }
 
-#[derive(Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
-#[serde(transparent)]
-pub(super) struct MediaHash(
-	#[serde(with = "serde_big_array::BigArray")] pub(super) database::Hash
-);
+/// The hash of some media. It is serialized as a hex string, as json object keys must
+/// be strings.
+#[derive(Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub(super) struct MediaHash(pub(super) database::Hash);
+
+impl Serialize for MediaHash {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer
+	{
+		let hex: String = self.0.iter().map(|byte| format!("{byte:02x}")).collect();
+		serializer.serialize_str(&hex)
+	}
+}
+
+impl<'de> Deserialize<'de> for MediaHash {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>
+	{
+		let hex = String::deserialize(deserializer)?;
+		let mut hash: database::Hash = [0; 64];
+		if hex.len() != 2 * hash.len() || !hex.is_ascii() {
+			return Err(D::Error::invalid_value(
+				de::Unexpected::Str(&hex),
+				&"a hex string of 128 digits"
+			));
+		}
+		for (i, byte) in hash.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&hex[2 * i .. 2 * i + 2], 16)
+				.map_err(D::Error::custom)?;
+		}
+		Ok(Self(hash))
+	}
+}
 
 impl Borrow<database::Hash> for MediaHash {
 	fn borrow(&self) -> &database::Hash {
@@ -210,9 +269,543 @@ pub(super) async fn write_media_map(
 	Ok(())
 }
 
+/// Where a sticker pack that was created by this bot came from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct PackSource {
+	#[serde(rename = "type")]
+	pub(super) ty: SourceType,
+	pub(super) url: String,
+	pub(super) imported_at: MilliSecondsSinceUnixEpoch,
+	pub(super) requester: OwnedUserId,
+	pub(super) bot_version: String
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum SourceType {
+	Telegram,
+	Maunium,
+	Archive,
+	Matrix
+}
+
+impl PackSource {
+	pub(super) fn new(ty: SourceType, url: String, requester: &UserId) -> Self {
+		Self {
+			ty,
+			url,
+			imported_at: MilliSecondsSinceUnixEpoch::now(),
+			requester: requester.to_owned(),
+			bot_version: env!("CARGO_PKG_VERSION").to_owned()
+		}
+	}
+
+	/// Whether both sources refer to the same original sticker pack.
+	pub(super) fn is_same(&self, other: &Self) -> bool {
+		self.ty == other.ty && self.url == other.url
+	}
+}
+
+/// A sticker pack as stored in the room state, including our own metadata.
+#[derive(Deserialize, Serialize)]
+pub(super) struct RoomStickerPack {
+	#[serde(flatten)]
+	pub(super) pack: ponies::StickerPack,
+
+	#[serde(
+		default,
+		rename = "de.msrd0.tg2mx_bot.source",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub(super) source: Option<PackSource>,
+
+	/// Only set on the parts of sticker packs that were split by [`write_stickerpack`].
+	#[serde(
+		default,
+		rename = "de.msrd0.tg2mx_bot.part",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub(super) part: Option<PackPart>
+}
+
+/// Identifies one part of a sticker pack that was split across several state events.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct PackPart {
+	/// The state key of the first part.
+	pub(super) id: String,
+	/// The index of this part, starting at 1.
+	pub(super) index: usize,
+	pub(super) count: usize
+}
+
+impl RoomStickerPack {
+	/// Whether this is the `index`th part of the split sticker pack `id`.
+	fn is_part_of(&self, id: &str, index: usize) -> bool {
+		self.part
+			.as_ref()
+			.is_some_and(|part| part.id == id && part.index == index)
+	}
+
+	/// Whether this pack was created from the source. Packs without source metadata
+	/// are identified by their display name.
+	pub(super) fn is_from(&self, source: &PackSource, display_name: &str) -> bool {
+		match &self.source {
+			Some(own) => own.is_same(source),
+			None => self.pack.pack.display_name == display_name
+		}
+	}
+}
+
+/// Use the first sticker as the avatar of the pack if it doesn't have one.
+pub(super) fn fill_avatar(pack: &mut ponies::StickerPack) {
+	if pack.pack.avatar_url.is_none() {
+		pack.pack.avatar_url = pack
+			.images
+			.values()
+			.next()
+			.map(|sticker| sticker.url.clone());
+	}
+}
+
 pub(super) async fn read_stickerpack(
 	room: &Room,
 	name: &str
-) -> anyhow::Result<Option<ponies::StickerPack>> {
+) -> anyhow::Result<Option<RoomStickerPack>> {
 	Ok(read_room_state(room, "im.ponies.room_emotes", Some(name)).await??)
 }
+
+/// Read the sticker pack from the homeserver. Our state store only learns about our
+/// own changes on the next sync, so it might not contain the latest edit yet.
+pub(super) async fn fetch_stickerpack(
+	room: &Room,
+	id: &str
+) -> anyhow::Result<Option<RoomStickerPack>> {
+	let request = get_state_events_for_key::v3::Request::new(
+		room.room_id().to_owned(),
+		"im.ponies.room_emotes".into(),
+		id.to_owned()
+	);
+	let content: serde_json::Value = match room.client().send(request, None).await {
+		Ok(response) => response.content.deserialize_as()?,
+		Err(err) if matches!(err.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+			return Ok(None);
+		},
+		Err(err) => return Err(err.into())
+	};
+	// an empty content means the sticker pack was removed
+	if content
+		.as_object()
+		.is_some_and(|content| content.is_empty())
+	{
+		return Ok(None);
+	}
+	Ok(Some(serde_json::from_value(content)?))
+}
+
+#[derive(Deserialize)]
+struct PackEvent {
+	state_key: String,
+	#[serde(default)]
+	content: serde_json::Value
+}
+
+/// Read all sticker packs of the room by their state key. The parts of split sticker
+/// packs are returned individually.
+pub(super) async fn read_all_stickerpacks<T>(
+	room: &Room
+) -> anyhow::Result<BTreeMap<String, T>>
+where
+	T: DeserializeOwned
+{
+	let events = room
+		.get_state_events("im.ponies.room_emotes".into())
+		.await?;
+	let mut packs = BTreeMap::new();
+	for ev in events {
+		let ev: PackEvent = match ev {
+			RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as()?,
+			RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as()?
+		};
+		// an empty content means the sticker pack was removed
+		if ev
+			.content
+			.as_object()
+			.is_some_and(|content| content.is_empty())
+		{
+			continue;
+		}
+		packs.insert(ev.state_key, serde_json::from_value(ev.content)?);
+	}
+	Ok(packs)
+}
+
+/// Matrix events are limited to 64 KiB. Leave some room for the event's metadata.
+const MAX_PACK_CONTENT_SIZE: usize = 60 * 1024;
+
+/// The state key of the `part`th part (starting at 1) of a sticker pack.
+fn stickerpack_part_key(id: &str, part: usize) -> String {
+	match part {
+		1 => id.to_owned(),
+		part => format!("{id}_{part}")
+	}
+}
+
+/// Read a sticker pack that might have been split into multiple parts by
+/// [`write_stickerpack`], and join the parts back together. Only state events that are
+/// marked as parts of the pack are joined.
+pub(super) async fn read_split_stickerpack(
+	room: &Room,
+	id: &str
+) -> anyhow::Result<Option<RoomStickerPack>> {
+	join_stickerpack_parts(
+		id,
+		move |key| async move { read_stickerpack(room, &key).await }
+	)
+	.await
+}
+
+/// Like [`read_split_stickerpack`], but read the parts from the homeserver.
+pub(super) async fn fetch_split_stickerpack(
+	room: &Room,
+	id: &str
+) -> anyhow::Result<Option<RoomStickerPack>> {
+	join_stickerpack_parts(
+		id,
+		move |key| async move { fetch_stickerpack(room, &key).await }
+	)
+	.await
+}
+
+/// Join the parts of a sticker pack, reading each state key with `read`.
+async fn join_stickerpack_parts<F, Fut>(
+	id: &str,
+	read: F
+) -> anyhow::Result<Option<RoomStickerPack>>
+where
+	F: Fn(String) -> Fut,
+	Fut: Future<Output = anyhow::Result<Option<RoomStickerPack>>>
+{
+	let Some(mut pack) = read(id.to_owned()).await? else {
+		return Ok(None);
+	};
+	let count = match pack.part.take() {
+		Some(part) if part.id == id && part.index == 1 => part.count,
+		Some(part) if part.id != id => bail!(
+			"{id} is part {} of the sticker pack {}, please use {} instead",
+			part.index,
+			part.id,
+			part.id
+		),
+		_ => 1
+	};
+	for index in 2 ..= count {
+		let key = stickerpack_part_key(id, index);
+		let Some(next) = read(key).await?.filter(|next| next.is_part_of(id, index))
+		else {
+			warn!("Part {index} of the sticker pack {id} is missing");
+			break;
+		};
+		pack.pack.images.extend(next.pack.images);
+	}
+
+	// remove the part suffix from the display name
+	let display_name = &mut pack.pack.pack.display_name;
+	if let Some(idx) = display_name.rfind(" (part 1/") {
+		if display_name.ends_with(')') {
+			display_name.truncate(idx);
+		}
+	}
+
+	Ok(Some(pack))
+}
+
+/// Return the shortcode, suffixed with a number if the pack already contains it.
+pub(super) fn unique_shortcode(
+	images: &IndexMap<String, ponies::Sticker>,
+	shortcode: &str
+) -> String {
+	let mut key = shortcode.to_owned();
+	let mut i = 2;
+	while images.contains_key(&key) {
+		key = format!("{shortcode}_{i}");
+		i += 1;
+	}
+	key
+}
+
+/// Add all stickers to the pack that it doesn't contain yet, comparing their urls.
+/// Existing shortcodes are kept. Returns the number of stickers that were added.
+pub(super) fn merge_stickers(
+	pack: &mut ponies::StickerPack,
+	stickers: IndexMap<String, ponies::Sticker>
+) -> usize {
+	let mut added = 0;
+	for (shortcode, sticker) in stickers {
+		if pack
+			.images
+			.values()
+			.any(|existing| existing.url == sticker.url)
+		{
+			continue;
+		}
+		let key = unique_shortcode(&pack.images, &shortcode);
+		pack.images.insert(key, sticker);
+		added += 1;
+	}
+	added
+}
+
+/// Split a sticker pack into parts that each fit into a state event.
+fn split_stickerpack(
+	id: &str,
+	pack: RoomStickerPack
+) -> anyhow::Result<Vec<RoomStickerPack>> {
+	// the size of the pack without any images, with room for the part suffix and marker
+	let empty = RoomStickerPack {
+		pack: ponies::StickerPack {
+			images: IndexMap::new(),
+			pack: ponies::PackInfo {
+				display_name: pack.pack.pack.display_name.clone(),
+				avatar_url: pack.pack.pack.avatar_url.clone()
+			}
+		},
+		source: pack.source.clone(),
+		part: Some(PackPart {
+			id: id.to_owned(),
+			index: 99,
+			count: 99
+		})
+	};
+	let base_size = serde_json::to_vec(&empty)?.len() + " (part 99/99)".len();
+
+	let mut parts: Vec<IndexMap<String, ponies::Sticker>> = vec![IndexMap::new()];
+	let mut size = base_size;
+	for (shortcode, sticker) in pack.pack.images {
+		// the entry is prefixed by a comma and the key is followed by a colon
+		let entry_size = serde_json::to_vec(&shortcode)?.len()
+			+ serde_json::to_vec(&sticker)?.len()
+			+ 2;
+		if base_size + entry_size > MAX_PACK_CONTENT_SIZE {
+			bail!("Sticker {shortcode} is too large to fit into a sticker pack");
+		}
+		if size + entry_size > MAX_PACK_CONTENT_SIZE {
+			parts.push(IndexMap::new());
+			size = base_size;
+		}
+		size += entry_size;
+		parts.last_mut().unwrap().insert(shortcode, sticker);
+	}
+
+	let count = parts.len();
+	Ok(parts
+		.into_iter()
+		.enumerate()
+		.map(|(i, images)| RoomStickerPack {
+			pack: ponies::StickerPack {
+				images,
+				pack: ponies::PackInfo {
+					display_name: match count {
+						1 => empty.pack.pack.display_name.clone(),
+						count => format!(
+							"{} (part {}/{count})",
+							empty.pack.pack.display_name,
+							i + 1
+						)
+					},
+					avatar_url: empty.pack.pack.avatar_url.clone()
+				}
+			},
+			source: empty.source.clone(),
+			part: (count > 1).then(|| PackPart {
+				id: id.to_owned(),
+				index: i + 1,
+				count
+			})
+		})
+		.collect())
+}
+
+/// Write a sticker pack to the room, splitting it across multiple state events if it
+/// is too large for one. Returns the state keys that were written.
+pub(super) async fn write_stickerpack(
+	room: &Room,
+	id: &str,
+	pack: RoomStickerPack
+) -> anyhow::Result<Vec<String>> {
+	let parts = split_stickerpack(id, pack)?;
+
+	// never overwrite a different sticker pack with one of the parts. Ask the
+	// homeserver, as our state store might not know about recent changes yet.
+	for index in 2 ..= parts.len() {
+		let key = stickerpack_part_key(id, index);
+		if fetch_stickerpack(room, &key)
+			.await?
+			.is_some_and(|pack| !pack.is_part_of(id, index))
+		{
+			bail!(
+				"The sticker pack needs to be split into {} parts, but a different sticker \
+				 pack already uses the state key {key}",
+				parts.len()
+			);
+		}
+	}
+
+	let mut keys = Vec::new();
+	for (i, part) in parts.into_iter().enumerate() {
+		let key = stickerpack_part_key(id, i + 1);
+		write_room_state(room, "im.ponies.room_emotes", Some(&key), part).await?;
+		keys.push(key);
+	}
+
+	// remove left-over parts in case the pack was previously split into more parts
+	remove_stickerpack_parts(room, id, keys.len() + 1).await?;
+
+	Ok(keys)
+}
+
+/// Remove all parts of a sticker pack, starting with `first_part`. Only the first part
+/// and state events that are marked as parts of the pack are removed.
+async fn remove_stickerpack_parts(
+	room: &Room,
+	id: &str,
+	first_part: usize
+) -> anyhow::Result<()> {
+	for index in first_part .. {
+		let key = stickerpack_part_key(id, index);
+		match fetch_stickerpack(room, &key).await? {
+			Some(pack) if index == 1 || pack.is_part_of(id, index) => {
+				write_room_state(room, "im.ponies.room_emotes", Some(&key), json!({}))
+					.await?;
+			},
+			_ => break
+		}
+	}
+	Ok(())
+}
+
+/// Remove a sticker pack, including all parts it was split into.
+pub(super) async fn remove_stickerpack(room: &Room, id: &str) -> anyhow::Result<()> {
+	remove_stickerpack_parts(room, id, 1).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	fn pack_with_stickers(count: usize) -> RoomStickerPack {
+		let images: serde_json::Map<String, serde_json::Value> = (0 .. count)
+			.map(|i| {
+				(
+					format!("sticker{i}"),
+					json!({
+						"body": "a rather long description of the sticker ".repeat(10),
+						"url": format!("mxc://example.org/{i}"),
+						"info": {
+							"w": 256,
+							"h": 256,
+							"size": 1234,
+							"mimetype": "image/png"
+						},
+						"usage": ["sticker"]
+					})
+				)
+			})
+			.collect();
+		serde_json::from_value(json!({
+			"images": images,
+			"pack": {
+				"display_name": "Cats"
+			}
+		}))
+		.unwrap()
+	}
+
+	fn shortcodes(pack: &RoomStickerPack) -> Vec<&str> {
+		pack.pack.images.keys().map(String::as_str).collect()
+	}
+
+	#[test]
+	fn small_pack_is_not_split() {
+		let parts = split_stickerpack("cats", pack_with_stickers(3)).unwrap();
+		assert_eq!(parts.len(), 1);
+		assert!(parts[0].part.is_none());
+		assert_eq!(parts[0].pack.pack.display_name, "Cats");
+		assert_eq!(shortcodes(&parts[0]), ["sticker0", "sticker1", "sticker2"]);
+	}
+
+	#[test]
+	fn split_parts_fit_into_state_events() {
+		let pack = pack_with_stickers(500);
+		let expected: Vec<String> = pack.pack.images.keys().cloned().collect();
+		let parts = split_stickerpack("cats", pack).unwrap();
+		let count = parts.len();
+		assert!(count > 1, "the pack should have been split");
+
+		let mut images = Vec::new();
+		for (i, part) in parts.iter().enumerate() {
+			let size = serde_json::to_vec(part).unwrap().len();
+			assert!(
+				size <= MAX_PACK_CONTENT_SIZE,
+				"part {} has {size} bytes",
+				i + 1
+			);
+			let marker = part.part.as_ref().unwrap();
+			assert_eq!(marker.id, "cats");
+			assert_eq!(marker.index, i + 1);
+			assert_eq!(marker.count, count);
+			assert_eq!(
+				part.pack.pack.display_name,
+				format!("Cats (part {}/{count})", i + 1)
+			);
+			images.extend(shortcodes(part).into_iter().map(String::from));
+		}
+		assert_eq!(images, expected);
+	}
+
+	/// Split the pack and store its parts under their state keys.
+	fn split_state(
+		id: &str,
+		pack: RoomStickerPack
+	) -> HashMap<String, serde_json::Value> {
+		split_stickerpack(id, pack)
+			.unwrap()
+			.into_iter()
+			.enumerate()
+			.map(|(i, part)| {
+				let key = stickerpack_part_key(id, i + 1);
+				(key, serde_json::to_value(part).unwrap())
+			})
+			.collect()
+	}
+
+	async fn join(
+		state: &HashMap<String, serde_json::Value>,
+		id: &str
+	) -> anyhow::Result<Option<RoomStickerPack>> {
+		join_stickerpack_parts(id, |key| {
+			let value = state.get(&key).cloned();
+			async move { Ok(value.map(serde_json::from_value).transpose()?) }
+		})
+		.await
+	}
+
+	#[tokio::test]
+	async fn join_split_parts() {
+		let pack = pack_with_stickers(500);
+		let expected: Vec<String> = pack.pack.images.keys().cloned().collect();
+		let state = split_state("cats", pack);
+		assert!(state.contains_key("cats_2"));
+
+		let joined = join(&state, "cats").await.unwrap().unwrap();
+		assert!(joined.part.is_none());
+		assert_eq!(joined.pack.pack.display_name, "Cats");
+		assert_eq!(shortcodes(&joined), expected);
+	}
+
+	#[tokio::test]
+	async fn refuse_to_join_from_a_later_part() {
+		let state = split_state("cats", pack_with_stickers(500));
+		assert!(join(&state, "cats_2").await.is_err());
+	}
+}
@@ -0,0 +1,92 @@
+use super::state::{
+	fetch_stickerpack, read_all_stickerpacks, write_room_state, RoomStickerPack
+};
+use crate::perm::{is_allowed, Command};
+use anyhow::{bail, Context as _};
+use log::warn;
+use matrix_sdk::{
+	room::Room,
+	ruma::{
+		api::client::state::get_state_events_for_key,
+		events::{room::create::RoomCreateEventContent, StateEventType},
+		RoomId, UserId
+	},
+	RoomState
+};
+use std::collections::BTreeMap;
+
+/// Check that the `m.room.create` event of the new room names the room as its
+/// predecessor. Our state store doesn't know the new room until the next sync, so ask
+/// the homeserver.
+async fn replaces(new_room: &Room, room: &Room) -> anyhow::Result<bool> {
+	let request = get_state_events_for_key::v3::Request::new(
+		new_room.room_id().to_owned(),
+		StateEventType::RoomCreate,
+		String::new()
+	);
+	let response = new_room.client().send(request, None).await?;
+	let content: RoomCreateEventContent = response.content.deserialize_as()?;
+	Ok(content
+		.predecessor
+		.is_some_and(|predecessor| predecessor.room_id == room.room_id()))
+}
+
+/// Join the room that replaces an upgraded room and copy the sticker packs that we
+/// manage, i.e. those with source metadata, into it. Unless we were invited to the new
+/// room, the upgrade must have been done by someone who may invite us. Returns the new
+/// room and the state keys that were copied, or `None` if the room has no sticker
+/// packs of ours.
+pub(super) async fn carry_over_packs(
+	room: &Room,
+	sender: &UserId,
+	replacement: &RoomId
+) -> anyhow::Result<Option<(Room, Vec<String>)>> {
+	// the parts of split sticker packs are copied as they are
+	let packs: BTreeMap<String, RoomStickerPack> = read_all_stickerpacks(room)
+		.await?
+		.into_iter()
+		.filter(|(_, pack)| pack.source.is_some())
+		.collect();
+	if packs.is_empty() {
+		return Ok(None);
+	}
+
+	let client = room.client();
+	let state = client.get_room(replacement).map(|room| room.state());
+	let was_joined = state == Some(RoomState::Joined);
+	if !was_joined
+		&& state != Some(RoomState::Invited)
+		&& !is_allowed(sender, Command::Invite)
+	{
+		bail!(
+			"{sender} may not invite me, so I didn't join the new room {replacement}. \
+			 Invite me and use !copy to copy the sticker packs instead."
+		);
+	}
+	let new_room = client.join_room_by_id(replacement).await.with_context(|| {
+		format!("Failed to join the new room {replacement}, please invite me")
+	})?;
+	if !replaces(&new_room, room).await? {
+		if !was_joined {
+			new_room.leave().await.ok();
+		}
+		bail!("The room {replacement} is not the successor of this room");
+	}
+
+	// our state store doesn't know the new room until the next sync, so we can't check
+	// our power level beforehand and ask the homeserver for existing packs instead
+	let mut keys = Vec::new();
+	for (key, pack) in packs {
+		if fetch_stickerpack(&new_room, &key).await?.is_some() {
+			warn!("Not overwriting the sticker pack {key} in {replacement}");
+			continue;
+		}
+		write_room_state(&new_room, "im.ponies.room_emotes", Some(&key), pack)
+			.await
+			.with_context(|| {
+				format!("Failed to add the sticker pack {key} to the new room")
+			})?;
+		keys.push(key);
+	}
+	Ok(Some((new_room, keys)))
+}
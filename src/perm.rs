@@ -0,0 +1,286 @@
+use crate::config::config;
+use matrix_sdk::ruma::UserId;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The roles a user can have, ordered from the least to the most privileged role.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+	User,
+	Admin,
+	Owner
+}
+
+/// The commands (and actions) that are subject to permission checks.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Command {
+	Help,
+	Import,
+	Migrate,
+	Avatar,
+	Add,
+	Pack,
+	Copy,
+	Move,
+	Export,
+	ClearQueue,
+	Backup,
+	Restore,
+	Invite
+}
+
+impl Command {
+	/// Whether the command changes the sticker packs of the room it was sent in.
+	pub(crate) fn changes_packs(self) -> bool {
+		match self {
+			Self::Import
+			| Self::Migrate
+			| Self::Avatar
+			| Self::Add
+			| Self::Pack
+			| Self::Move => true,
+			Self::Help
+			| Self::Copy
+			| Self::Export
+			| Self::ClearQueue
+			| Self::Backup
+			| Self::Restore
+			| Self::Invite => false
+		}
+	}
+
+	fn default_role(self) -> Role {
+		match self {
+			Self::Help
+			| Self::Import
+			| Self::Migrate
+			| Self::Avatar
+			| Self::Add
+			| Self::Pack
+			| Self::Copy
+			| Self::Move
+			| Self::Export => Role::User,
+			Self::ClearQueue | Self::Backup | Self::Restore | Self::Invite => Role::Admin
+		}
+	}
+}
+
+/// A glob pattern that either matches user ids (if it starts with an `@`) or the
+/// server name of a user id. The wildcards `*` and `?` are supported. Server names are
+/// matched case-insensitively.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Pattern(String);
+
+impl Pattern {
+	pub(crate) fn new(pattern: impl Into<String>) -> Self {
+		Self(pattern.into())
+	}
+
+	fn matches(&self, user: &UserId) -> bool {
+		// server names are case-insensitive, but localparts are not
+		let server_name = user.server_name().as_str().to_lowercase();
+		match self.0.split_once(':') {
+			Some((localpart, server)) if self.0.starts_with('@') => {
+				glob_match(localpart, &format!("@{}", user.localpart()))
+					&& glob_match(&server.to_lowercase(), &server_name)
+			},
+			_ => glob_match(&self.0.to_lowercase(), &server_name)
+		}
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if self.0.is_empty() {
+			return Err("Empty pattern".into());
+		}
+		if self.0.starts_with('@') && !self.0.contains(':') {
+			return Err(format!(
+				"User id pattern {:?} is missing a server name",
+				self.0
+			));
+		}
+		if !self.0.starts_with('@') && self.0.contains(['@', ':']) {
+			return Err(format!(
+				"Server name pattern {:?} must not contain '@' or ':'",
+				self.0
+			));
+		}
+		Ok(())
+	}
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+	let (mut p, mut t) = (0, 0);
+	// position of the last * in the pattern and the text position it matched at
+	let mut backtrack = None;
+	while t < text.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				backtrack = Some((p, t));
+				p += 1;
+			},
+			Some(ch) if *ch == '?' || *ch == text[t] => {
+				p += 1;
+				t += 1;
+			},
+			_ => match backtrack {
+				Some((star_p, star_t)) => {
+					p = star_p + 1;
+					t = star_t + 1;
+					backtrack = Some((star_p, star_t + 1));
+				},
+				None => return false
+			}
+		}
+	}
+	pattern[p ..].iter().all(|ch| *ch == '*')
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Permissions {
+	pub(crate) owner: Vec<Pattern>,
+	pub(crate) admin: Vec<Pattern>,
+	pub(crate) user: Vec<Pattern>,
+
+	/// Allow running without any owner or admin, in which case everyone is an admin.
+	pub(crate) anyone_is_admin: bool,
+
+	/// The power level required to manage the sticker packs of a room. By default,
+	/// the power level needed to send `im.ponies.room_emotes` state is required.
+	pub(crate) pack_power_level: Option<i64>,
+
+	/// The role required to use a command, overriding the default.
+	pub(crate) commands: HashMap<Command, Role>
+}
+
+impl Default for Permissions {
+	fn default() -> Self {
+		Self {
+			owner: Vec::new(),
+			admin: Vec::new(),
+			user: vec![Pattern::new("*")],
+			anyone_is_admin: false,
+			pack_power_level: None,
+			commands: HashMap::new()
+		}
+	}
+}
+
+impl Permissions {
+	fn has_admin(&self) -> bool {
+		!self.owner.is_empty() || !self.admin.is_empty()
+	}
+
+	pub(crate) fn validate(&self, errors: &mut Vec<String>) {
+		for (role, patterns) in [
+			("owner", &self.owner),
+			("admin", &self.admin),
+			("user", &self.user)
+		] {
+			for pattern in patterns {
+				if let Err(err) = pattern.validate() {
+					errors.push(format!("permissions.{role}: {err}"));
+				}
+			}
+		}
+
+		if !self.has_admin() && !self.anyone_is_admin {
+			errors.push(
+				"permissions: No owner or admin configured. Set \
+				 permissions.anyone_is_admin = true if you really want everyone to be \
+				 an admin."
+					.into()
+			);
+		}
+	}
+
+	/// Return the most privileged role of the user, if any.
+	pub(crate) fn role_of(&self, user: &UserId) -> Option<Role> {
+		if !self.has_admin() && self.anyone_is_admin {
+			return Some(Role::Admin);
+		}
+
+		[
+			(Role::Owner, &self.owner),
+			(Role::Admin, &self.admin),
+			(Role::User, &self.user)
+		]
+		.into_iter()
+		.find(|(_, patterns)| patterns.iter().any(|pattern| pattern.matches(user)))
+		.map(|(role, _)| role)
+	}
+
+	/// Return the role required to use the command.
+	pub(crate) fn required_role(&self, cmd: Command) -> Role {
+		self.commands
+			.get(&cmd)
+			.copied()
+			.unwrap_or_else(|| cmd.default_role())
+	}
+}
+
+/// Check whether the user is allowed to use the command.
+pub(crate) fn is_allowed(user: &UserId, cmd: Command) -> bool {
+	let perms = &config().permissions;
+	perms
+		.role_of(user)
+		.is_some_and(|role| role >= perms.required_role(cmd))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use matrix_sdk::ruma::user_id;
+
+	#[test]
+	fn glob_wildcards() {
+		assert!(glob_match("*", ""));
+		assert!(glob_match("*", "anything"));
+		assert!(glob_match("a*c", "abbbc"));
+		assert!(glob_match("a*c", "ac"));
+		assert!(glob_match("a?c", "abc"));
+		assert!(!glob_match("a?c", "ac"));
+		assert!(glob_match("*.example.org", "matrix.example.org"));
+		assert!(!glob_match("*.example.org", "example.org"));
+		assert!(glob_match("*b*b", "abab"));
+		assert!(!glob_match("abc", "abcd"));
+		assert!(!glob_match("abcd", "abc"));
+	}
+
+	#[test]
+	fn user_id_pattern() {
+		let pattern = Pattern::new("@*:example.org");
+		assert!(pattern.matches(user_id!("@alice:example.org")));
+		assert!(!pattern.matches(user_id!("@alice:example.org.evil")));
+		assert!(!pattern.matches(user_id!("@alice:evil.example.org")));
+
+		let pattern = Pattern::new("@alice:example.org");
+		assert!(pattern.matches(user_id!("@alice:example.org")));
+		assert!(!pattern.matches(user_id!("@alice2:example.org")));
+		assert!(!pattern.matches(user_id!("@bob:example.org")));
+	}
+
+	#[test]
+	fn server_name_pattern() {
+		let pattern = Pattern::new("example.org");
+		assert!(pattern.matches(user_id!("@alice:example.org")));
+		assert!(!pattern.matches(user_id!("@alice:example.org.evil")));
+		assert!(!pattern.matches(user_id!("@alice:notexample.org")));
+
+		let pattern = Pattern::new("*.example.org");
+		assert!(pattern.matches(user_id!("@alice:matrix.example.org")));
+		assert!(!pattern.matches(user_id!("@alice:example.org")));
+	}
+
+	#[test]
+	fn server_names_ignore_case() {
+		assert!(Pattern::new("Example.ORG").matches(user_id!("@alice:example.org")));
+		assert!(Pattern::new("example.org").matches(user_id!("@alice:EXAMPLE.org")));
+		assert!(Pattern::new("@*:example.org").matches(user_id!("@alice:Example.Org")));
+	}
+}